@@ -1,44 +1,280 @@
 // Abstract Syntax Tree definitions
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Byte range in the source file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Program {
+    /// Other files this one depends on; emptied once modules are merged
+    pub imports: Vec<Import>,
+    pub uses: Vec<Use>,
     pub types: Vec<TypeDef>,
+    pub functions: Vec<FnDef>,
     pub agents: Vec<AgentDef>,
+    pub supervisors: Vec<SupervisorDef>,
+}
+
+/// A top-level declaration, in source order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Item {
+    Type(TypeDef),
+    Function(FnDef),
+    Agent(AgentDef),
+    Supervisor(SupervisorDef),
+    Import(Import),
+    Use(Use),
+}
+
+/// `import "types.agent";`, a path relative to the importing file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Import {
+    pub path: String,
+    pub span: Span,
+}
+
+/// `use module::Name;` brings a public item of an imported module into scope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Use {
+    pub module: String,
+    pub name: String,
+    pub span: Span,
+}
+
+impl FromIterator<Item> for Program {
+    fn from_iter<I: IntoIterator<Item = Item>>(items: I) -> Self {
+        let mut program = Program::default();
+        for item in items {
+            match item {
+                Item::Type(type_def) => program.types.push(type_def),
+                Item::Function(function) => program.functions.push(function),
+                Item::Agent(agent) => program.agents.push(agent),
+                Item::Supervisor(supervisor) => program.supervisors.push(supervisor),
+                Item::Import(import) => program.imports.push(import),
+                Item::Use(use_decl) => program.uses.push(use_decl),
+            }
+        }
+        program
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TypeDef {
     pub name: String,
+    /// Visible to other modules
+    pub public: bool,
+    /// Type parameters of a generic type, e.g. `T` in `type Reply[T]`
+    pub params: Vec<String>,
     pub variants: Vec<Variant>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Variant {
     pub name: String,
     pub fields: Vec<Field>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Field {
     pub name: String,
     pub ty: Type,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Type {
     Int,
+    Float,
     String,
     Bool,
-    Ref(String),   // Ref[MessageType]
-    Named(String), // User-defined type
+    /// A span of time, e.g. `30s`
+    Duration,
+    /// Handle to a pending `schedule` or `every`, for cancelling it
+    Timer,
+    /// `Ref[MessageType]`, with the type arguments of a generic message type
+    Ref(String, Vec<Type>),
+    /// User-defined type, e.g. `Reply[Int]`
+    Named(String, Vec<Type>),
+    /// A type parameter of the enclosing generic type or agent
+    Param(String),
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Option(Box<Type>),
+    /// Element type of an empty literal (`[]`, `{}`, `none`), fixed by context
+    Infer,
+    /// Placeholder for an expression that failed to type check
+    Error,
+}
+
+impl Type {
+    /// Whether an `Infer` hole remains anywhere in the type
+    pub fn has_holes(&self) -> bool {
+        match self {
+            Type::Infer => true,
+            Type::List(elem) | Type::Option(elem) => elem.has_holes(),
+            Type::Map(key, value) => key.has_holes() || value.has_holes(),
+            Type::Ref(_, args) | Type::Named(_, args) => args.iter().any(Type::has_holes),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::Duration => write!(f, "Duration"),
+            Type::Timer => write!(f, "Timer"),
+            Type::String => write!(f, "String"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Ref(name, args) => write!(f, "Ref[{}{}]", name, TypeArgs(args)),
+            Type::Named(name, args) => write!(f, "{}{}", name, TypeArgs(args)),
+            Type::Param(name) => write!(f, "{}", name),
+            Type::List(elem) => write!(f, "List[{}]", elem),
+            Type::Map(key, value) => write!(f, "Map[{}, {}]", key, value),
+            Type::Option(elem) => write!(f, "Option[{}]", elem),
+            Type::Infer => write!(f, "_"),
+            Type::Error => write!(f, "{{error}}"),
+        }
+    }
+}
+
+/// Displays as `[A, B]`, or nothing for a non-generic type
+struct TypeArgs<'a>(&'a [Type]);
+
+impl fmt::Display for TypeArgs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let args: Vec<String> = self.0.iter().map(Type::to_string).collect();
+        write!(f, "[{}]", args.join(", "))
+    }
+}
+
+/// `fn name(p: T) -> R uses log { ... }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FnDef {
+    pub name: String,
+    pub public: bool,
+    pub params: Vec<Param>,
+    /// `None` for functions called only as statements
+    pub ret: Option<Type>,
+    /// Effects the body may perform, from the `uses` clause
+    pub effects: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentDef {
     pub name: String,
+    pub public: bool,
+    /// Type parameters of a generic agent, e.g. `K, V` in `agent Cache[K, V]`
+    pub type_params: Vec<String>,
+    /// Constructor parameters, given to `spawn` and readable from state
+    /// initializers and handlers; agents with any are not started by name
+    pub params: Vec<Param>,
+    /// Message type the agent accepts, if declared with `agent Name : Type`
+    pub protocol: Option<String>,
+    /// Type arguments of a generic protocol, e.g. `K, V` in `: CacheMsg[K, V]`
+    pub protocol_args: Vec<Type>,
     pub state: Vec<StateVar>,
     pub handlers: Vec<Handler>,
+    /// `on start`, `on stop` and `on restart` hooks, in source order
+    pub hooks: Vec<LifecycleHook>,
+    pub span: Span,
+}
+
+/// One entry of an agent body, in source order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AgentMember {
+    Handler(Handler),
+    Hook(LifecycleHook),
+}
+
+/// `on start -> { }` runs before an instance handles its first message,
+/// `on stop -> { }` once it stops handling them, and `on restart -> { }`
+/// before `on start` when a supervisor restarts it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleHook {
+    pub event: LifecycleEvent,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LifecycleEvent {
+    Start,
+    Stop,
+    Restart,
+}
+
+impl fmt::Display for LifecycleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LifecycleEvent::Start => write!(f, "start"),
+            LifecycleEvent::Stop => write!(f, "stop"),
+            LifecycleEvent::Restart => write!(f, "restart"),
+        }
+    }
+}
+
+/// `supervisor Name { strategy: .., max_restarts: 3 within 10s, children { .. } }`
+/// starts its children at startup and restarts them when they crash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupervisorDef {
+    pub name: String,
+    pub public: bool,
+    pub strategy: Strategy,
+    /// The runtime's default applies if not given
+    pub max_restarts: Option<RestartLimit>,
+    /// Agents and nested supervisors, in start order
+    pub children: Vec<SupervisorChild>,
+    pub span: Span,
+}
+
+/// Which children restart when one of them crashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Strategy {
+    OneForOne,
+    OneForAll,
+    RestForOne,
+}
+
+/// More restarts than `count` within `window` make the supervisor give up
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RestartLimit {
+    pub count: usize,
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupervisorChild {
+    pub name: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +282,7 @@ pub struct StateVar {
     pub name: String,
     pub ty: Type,
     pub init: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,31 +290,193 @@ pub struct Handler {
     pub variant: String,
     pub params: Vec<String>,
     pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Stmt {
-    Assign { target: String, value: Expr },
+pub enum StmtKind {
+    /// `let name: Type = value;` with the annotation optional
+    Let {
+        name: String,
+        ty: Option<Type>,
+        value: Expr,
+    },
+    Assign { target: AssignTarget, value: Expr },
     Send {
         target: Expr,
         msg_variant: String,
-        args: Vec<Expr>,
+        args: Vec<(String, Expr)>,
     },
+    /// `name(args);`, either an effect or a call to a function
     Effect { name: String, args: Vec<Expr> },
+    /// `else if` chains nest another `If` as the sole else statement
+    If {
+        cond: Expr,
+        then_body: Vec<Stmt>,
+        else_body: Vec<Stmt>,
+    },
+    Match { scrutinee: Expr, arms: Vec<MatchArm> },
+    While { cond: Expr, body: Vec<Stmt> },
+    For {
+        var: String,
+        iter: ForIter,
+        body: Vec<Stmt>,
+    },
+    Break,
+    Continue,
+    /// Leaves the function (with its result) or the handler
+    Return(Option<Expr>),
+    /// An expression evaluated for its effect, e.g. `queue.push(id);`
+    Expr(Expr),
 }
 
+/// What a `for` loop walks over
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Expr {
+pub enum ForIter {
+    /// `start..end`, end exclusive
+    Range { start: Expr, end: Expr },
+    /// Every element of a list, in order
+    List(Expr),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Pattern {
+    /// `Variant { field, ... }` binds the named fields as locals
+    Variant { name: String, bindings: Vec<String> },
+    /// `some(x)` binds the contained value
+    Some(String),
+    /// `none`
+    None,
+    /// `_`
+    Wildcard,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AssignTarget {
+    /// `state.field = ...`
+    State(String),
+    /// A handler local
     Var(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    pub fn binop(op: BinOp, left: Expr, right: Expr, span: Span) -> Self {
+        Self::new(
+            ExprKind::BinOp {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+            span,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ExprKind {
+    Var(String),
+    /// `state.field`
+    StateField(String),
     Int(i64),
+    Float(f64),
+    /// `500ms`, `30s`, `5m`, `1h`
+    Duration(Duration),
     Str(String),
+    /// `"ticket ${id}"`; built by concatenating the parts
+    Interpolate(Vec<StrPart>),
     Bool(bool),
     BinOp {
         op: BinOp,
         left: Box<Expr>,
         right: Box<Expr>,
     },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
     FieldAccess { obj: Box<Expr>, field: String },
+    /// `Type::Variant { field: expr, ... }`
+    Construct {
+        type_name: String,
+        variant: String,
+        fields: Vec<(String, Expr)>,
+    },
+    /// `[a, b, c]`
+    List(Vec<Expr>),
+    /// `{k: v, ...}`
+    Map(Vec<(Expr, Expr)>),
+    /// `some(x)`
+    Some(Box<Expr>),
+    /// `none`
+    None,
+    /// `collection[index]`
+    Index { obj: Box<Expr>, index: Box<Expr> },
+    /// `name(args)`, a call to a top-level function
+    Call { name: String, args: Vec<Expr> },
+    /// The running agent's own ref
+    SelfRef,
+    /// `spawn Worker(args)`, or `spawn Cache[Int, String](args)` for a generic agent
+    Spawn {
+        agent: String,
+        type_args: Vec<Type>,
+        args: Vec<Expr>,
+    },
+    /// `ask target Variant { .. } within 2s`: send with a fresh ref as the
+    /// variant's `replyTo` field, and wait for what is sent to it
+    Ask {
+        target: Box<Expr>,
+        msg_variant: String,
+        args: Vec<(String, Expr)>,
+        timeout: Box<Expr>,
+    },
+    /// `schedule 5s send target Variant { .. }` delivers the message once
+    /// after the delay; `every 5s send ..` delivers it each period until
+    /// cancelled. Both evaluate to a `Timer`.
+    Schedule {
+        delay: Box<Expr>,
+        repeat: bool,
+        target: Box<Expr>,
+        msg_variant: String,
+        args: Vec<(String, Expr)>,
+    },
+    /// `receiver.method(args)` for built-in collection methods
+    MethodCall {
+        receiver: Box<Expr>,
+        method: String,
+        args: Vec<Expr>,
+    },
+}
+
+/// Field of a request variant that `ask` fills in with its temporary ref
+pub const REPLY_FIELD: &str = "replyTo";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StrPart {
+    Lit(String),
+    Expr(Expr),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -86,8 +485,50 @@ pub enum BinOp {
     Sub,
     Mul,
     Div,
+    Mod,
     Eq,
     Ne,
     Lt,
     Gt,
+    Le,
+    Ge,
+    /// Short-circuiting; compiled to jumps rather than a `BinOp` instruction
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+            BinOp::Le => "<=",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnaryOp::Neg => write!(f, "-"),
+            UnaryOp::Not => write!(f, "!"),
+        }
+    }
 }
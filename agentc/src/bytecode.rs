@@ -2,162 +2,818 @@
 use crate::ast::*;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BytecodeProgram {
+    /// Indexed by `Instruction::Call`
+    pub functions: Vec<BytecodeFunction>,
     pub agents: Vec<BytecodeAgent>,
+    pub supervisors: Vec<BytecodeSupervisor>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BytecodeFunction {
+    pub name: String,
+    /// Arguments occupy local slots `0..arity`
+    pub arity: usize,
+    pub num_locals: usize,
+    /// Whether `Return` hands a value back to the caller
+    pub returns_value: bool,
+    pub instructions: Vec<Instruction>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BytecodeAgent {
     pub name: String,
-    pub state_init: Vec<(String, Value)>,
+    /// Constructor parameters, in the order `Spawn` pops them
+    pub params: Vec<String>,
+    /// Computes the initial state when the agent is spawned
+    pub init: BytecodeHandler,
     pub handlers: Vec<BytecodeHandler>,
+    /// Runs before the first message is handled
+    pub on_start: Option<BytecodeHandler>,
+    /// Runs once the instance stops handling messages
+    pub on_stop: Option<BytecodeHandler>,
+    /// Runs before `on_start` when a supervisor restarts the instance
+    pub on_restart: Option<BytecodeHandler>,
+}
+
+/// A supervision tree node, built by the interpreter at startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BytecodeSupervisor {
+    pub name: String,
+    pub strategy: Strategy,
+    pub max_restarts: Option<RestartLimit>,
+    /// Names of agents and nested supervisors, in start order
+    pub children: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BytecodeHandler {
+    /// The variant handled, or `init` for the state initializers
     pub variant: String,
+    /// Bound to local slots `0..params.len()` from the message fields
     pub params: Vec<String>,
+    pub num_locals: usize,
     pub instructions: Vec<Instruction>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Instruction {
-    LoadVar(String),
+    LoadLocal(usize),
+    StoreLocal(usize),
+    /// Push a ref to the agent spawned under this name
+    LoadAgent(String),
+    /// Push a ref to the agent running the handler
+    LoadSelf,
+    /// Push a constructor argument of the running agent
+    LoadParam(usize),
+    /// Pop the constructor arguments, start a new instance of the named
+    /// agent and push a ref to it
+    Spawn { agent: String, arg_count: usize },
+    LoadState(String),
+    StoreState(String),
     LoadConst(Value),
-    Store(String),
     BinOp(BinOp),
-    Send {
-        target_var: String,
+    UnaryOp(UnaryOp),
+    /// Jump to an absolute instruction index; backward jumps close loops
+    Jump(usize),
+    /// Pop a Bool and jump if it is false
+    JumpIfFalse(usize),
+    /// Pop a Bool and jump if it is true
+    JumpIfTrue(usize),
+    /// Pop one value per field (in order) and push a message
+    MakeMessage {
+        variant: String,
+        fields: Vec<String>,
+    },
+    /// Pop a message, then the target ref, and deliver it
+    Send,
+    /// Pop the timeout, one value per field (in order), then the target ref;
+    /// send the message with a fresh reply ref as its `replyTo` field and
+    /// push the reply as an option, `none` if nothing came in time
+    Ask { variant: String, fields: Vec<String> },
+    /// Pop the delay, one value per field (in order), then the target ref;
+    /// arm a timer delivering the message once, or every `delay` if
+    /// `repeat`, and push its handle
+    Schedule {
         variant: String,
+        fields: Vec<String>,
+        repeat: bool,
     },
     Effect {
         name: String,
         arg_count: usize,
     },
     FieldAccess(String),
+    Dup,
+    Pop,
+    /// Pop a message and push whether it is of the given variant
+    IsVariant(String),
+    /// Pop this many values (first pushed first) and push them as a list
+    MakeList(usize),
+    /// Pop this many key/value pairs and push them as a map
+    MakeMap(usize),
+    /// Wrap the top of the stack in `some(..)`
+    MakeSome,
+    /// Pop an option and push whether it holds a value
+    IsSome,
+    /// Replace `some(x)` on top of the stack with `x`
+    Unwrap,
+    /// Pop an index (or key), then a collection, and push the element
+    Index,
+    /// Replace the top of the stack with its display string
+    ToStr,
+    /// Pop the arguments and run a function in a new frame
+    Call { function: usize, arg_count: usize },
+    /// Leave the current frame, passing its result (if any) to the caller
+    Return,
+    /// Pop the arguments, then the receiver, and push the result; methods
+    /// that update the receiver push the updated collection instead
+    CallMethod { name: String, arg_count: usize },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Value {
     Int(i64),
+    Float(f64),
+    Duration(Duration),
     Str(String),
     Bool(bool),
+    Message {
+        variant: String,
+        fields: Vec<(String, Value)>,
+    },
+    /// Address of a running agent
+    Ref(u64),
+    /// Handle to an armed timer
+    Timer(u64),
+    List(Vec<Value>),
+    /// Entries in insertion order
+    Map(Vec<(Value, Value)>),
+    Option(Option<Box<Value>>),
 }
 
+/// Compile a type-checked program. Type arguments are erased: a generic
+/// agent compiles to one set of handlers shared by every instantiation.
 pub fn compile(program: &Program) -> Result<BytecodeProgram> {
-    let mut agents = Vec::new();
+    let functions: Functions = program
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(index, function)| (function.name.clone(), (index, function.ret.is_some())))
+        .collect();
+
+    let mut compiled = Vec::new();
+    for function in &program.functions {
+        compiled.push(compile_function(function, &functions)?);
+    }
 
+    let mut agents = Vec::new();
     for agent in &program.agents {
-        agents.push(compile_agent(agent)?);
+        agents.push(compile_agent(agent, &functions)?);
     }
 
-    Ok(BytecodeProgram { agents })
+    let supervisors = program
+        .supervisors
+        .iter()
+        .map(|supervisor| BytecodeSupervisor {
+            name: supervisor.name.clone(),
+            strategy: supervisor.strategy,
+            max_restarts: supervisor.max_restarts,
+            children: supervisor.children.iter().map(|child| child.name.clone()).collect(),
+        })
+        .collect();
+
+    Ok(BytecodeProgram {
+        functions: compiled,
+        agents,
+        supervisors,
+    })
 }
 
-fn compile_agent(agent: &AgentDef) -> Result<BytecodeAgent> {
-    let mut state_init = Vec::new();
+/// Function name to its index and whether it returns a value
+type Functions = HashMap<String, (usize, bool)>;
+
+fn compile_function(function: &FnDef, functions: &Functions) -> Result<BytecodeFunction> {
+    let mut compiler = Compiler {
+        functions: functions.clone(),
+        ..Compiler::default()
+    };
+    compiler.push_scope();
+    for param in &function.params {
+        compiler.declare(&param.name);
+    }
 
+    for stmt in &function.body {
+        compiler.compile_stmt(stmt)?;
+    }
+
+    Ok(BytecodeFunction {
+        name: function.name.clone(),
+        arity: function.params.len(),
+        num_locals: compiler.num_locals,
+        returns_value: function.ret.is_some(),
+        instructions: compiler.instructions,
+    })
+}
+
+fn compile_agent(agent: &AgentDef, functions: &Functions) -> Result<BytecodeAgent> {
+    let params: Vec<String> = agent.params.iter().map(|p| p.name.clone()).collect();
+
+    // State fields are initialized in declaration order
+    let mut compiler = Compiler {
+        functions: functions.clone(),
+        params: params.clone(),
+        ..Compiler::default()
+    };
     for state_var in &agent.state {
-        let value = eval_const_expr(&state_var.init)?;
-        state_init.push((state_var.name.clone(), value));
+        compiler.compile_expr(&state_var.init)?;
+        compiler.emit(Instruction::StoreState(state_var.name.clone()));
     }
+    let init = BytecodeHandler {
+        variant: "init".to_string(),
+        params: Vec::new(),
+        num_locals: compiler.num_locals,
+        instructions: compiler.instructions,
+    };
 
     let mut handlers = Vec::new();
     for handler in &agent.handlers {
-        handlers.push(compile_handler(handler)?);
+        handlers.push(compile_handler(handler, &params, functions)?);
     }
 
+    // Hooks compile as handlers of a message without fields
+    let hook = |event: LifecycleEvent| {
+        let hook = agent.hooks.iter().find(|h| h.event == event)?;
+        let handler = Handler {
+            variant: event.to_string(),
+            params: Vec::new(),
+            body: hook.body.clone(),
+            span: hook.span,
+        };
+        Some(compile_handler(&handler, &params, functions))
+    };
+    let on_start = hook(LifecycleEvent::Start).transpose()?;
+    let on_stop = hook(LifecycleEvent::Stop).transpose()?;
+    let on_restart = hook(LifecycleEvent::Restart).transpose()?;
+
     Ok(BytecodeAgent {
         name: agent.name.clone(),
-        state_init,
+        params,
+        init,
         handlers,
+        on_start,
+        on_stop,
+        on_restart,
     })
 }
 
-fn compile_handler(handler: &Handler) -> Result<BytecodeHandler> {
-    let mut instructions = Vec::new();
+fn compile_handler(handler: &Handler, params: &[String], functions: &Functions) -> Result<BytecodeHandler> {
+    let mut compiler = Compiler {
+        functions: functions.clone(),
+        params: params.to_vec(),
+        ..Compiler::default()
+    };
+    compiler.push_scope();
+    for param in &handler.params {
+        compiler.declare(param);
+    }
 
     for stmt in &handler.body {
-        compile_stmt(stmt, &mut instructions)?;
+        compiler.compile_stmt(stmt)?;
     }
 
     Ok(BytecodeHandler {
         variant: handler.variant.clone(),
         params: handler.params.clone(),
-        instructions,
+        num_locals: compiler.num_locals,
+        instructions: compiler.instructions,
     })
 }
 
-fn compile_stmt(stmt: &Stmt, instructions: &mut Vec<Instruction>) -> Result<()> {
-    match stmt {
-        Stmt::Assign { target, value } => {
-            compile_expr(value, instructions)?;
-            instructions.push(Instruction::Store(target.clone()));
+/// Compiles one handler body, assigning every local its own slot
+#[derive(Default)]
+struct Compiler {
+    instructions: Vec<Instruction>,
+    /// Innermost scope last; maps local names to slots
+    scopes: Vec<HashMap<String, usize>>,
+    num_locals: usize,
+    /// Enclosing loops, innermost last
+    loops: Vec<LoopJumps>,
+    functions: Functions,
+    /// Constructor parameters of the agent, by `LoadParam` index
+    params: Vec<String>,
+}
+
+/// Pending `break`/`continue` jumps of a loop being compiled
+#[derive(Default)]
+struct LoopJumps {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+impl Compiler {
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Allocate a fresh slot for a local in the innermost scope
+    fn declare(&mut self, name: &str) -> usize {
+        let slot = self.num_locals;
+        self.num_locals += 1;
+        self.scopes
+            .last_mut()
+            .expect("a scope is open")
+            .insert(name.to_string(), slot);
+        slot
+    }
+
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).copied())
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.instructions.push(instruction);
+        self.instructions.len() - 1
+    }
+
+    /// Point a previously emitted jump at the next instruction to be emitted
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.instructions.len();
+        match &mut self.instructions[at] {
+            Instruction::Jump(t) | Instruction::JumpIfFalse(t) | Instruction::JumpIfTrue(t) => *t = target,
+            other => panic!("patching non-jump instruction {:?}", other),
         }
-        Stmt::Send {
-            target,
-            msg_variant,
-            args,
-        } => {
-            // Load target ref
-            if let Expr::Var(target_var) = target {
-                // Load args (simplified: assume all args are expressions)
-                for arg in args {
-                    compile_expr(arg, instructions)?;
-                }
-                instructions.push(Instruction::Send {
-                    target_var: target_var.clone(),
-                    variant: msg_variant.clone(),
-                });
+    }
+
+    /// Compile a loop body, then point its `continue`s at `continue_to`
+    /// (or, if `None`, at the code emitted right after the body)
+    fn compile_loop_body(&mut self, body: &[Stmt], continue_to: Option<usize>) -> Result<LoopJumps> {
+        self.loops.push(LoopJumps::default());
+        self.compile_block(body)?;
+        let jumps = self.loops.pop().expect("loop was pushed");
+        for &at in &jumps.continues {
+            match continue_to {
+                Some(target) => self.instructions[at] = Instruction::Jump(target),
+                None => self.patch_jump(at),
             }
         }
-        Stmt::Effect { name, args } => {
-            for arg in args {
-                compile_expr(arg, instructions)?;
-            }
-            instructions.push(Instruction::Effect {
-                name: name.clone(),
-                arg_count: args.len(),
-            });
-        }
+        Ok(jumps)
     }
-    Ok(())
-}
 
-fn compile_expr(expr: &Expr, instructions: &mut Vec<Instruction>) -> Result<()> {
-    match expr {
-        Expr::Var(name) => {
-            instructions.push(Instruction::LoadVar(name.clone()));
+    /// Emit a call, returning whether it leaves a result on the stack
+    fn compile_call(&mut self, name: &str, args: &[Expr]) -> Result<bool> {
+        let (function, returns_value) = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown function: {}", name))?;
+        for arg in args {
+            self.compile_expr(arg)?;
         }
-        Expr::Int(n) => {
-            instructions.push(Instruction::LoadConst(Value::Int(*n)));
-        }
-        Expr::Str(s) => {
-            instructions.push(Instruction::LoadConst(Value::Str(s.clone())));
-        }
-        Expr::Bool(b) => {
-            instructions.push(Instruction::LoadConst(Value::Bool(*b)));
+        self.emit(Instruction::Call {
+            function,
+            arg_count: args.len(),
+        });
+        Ok(returns_value)
+    }
+
+    fn compile_block(&mut self, body: &[Stmt]) -> Result<()> {
+        self.push_scope();
+        for stmt in body {
+            self.compile_stmt(stmt)?;
         }
-        Expr::BinOp { op, left, right } => {
-            compile_expr(left, instructions)?;
-            compile_expr(right, instructions)?;
-            instructions.push(Instruction::BinOp(op.clone()));
+        self.pop_scope();
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match &stmt.kind {
+            StmtKind::Let { name, ty: _, value } => {
+                self.compile_expr(value)?;
+                let slot = self.declare(name);
+                self.emit(Instruction::StoreLocal(slot));
+            }
+            StmtKind::Assign { target, value } => {
+                self.compile_expr(value)?;
+                let instruction = match target {
+                    AssignTarget::State(field) => Instruction::StoreState(field.clone()),
+                    AssignTarget::Var(name) => match self.resolve(name) {
+                        Some(slot) => Instruction::StoreLocal(slot),
+                        None => anyhow::bail!("Undefined variable: {}", name),
+                    },
+                };
+                self.emit(instruction);
+            }
+            StmtKind::Send {
+                target,
+                msg_variant,
+                args,
+            } => {
+                self.compile_expr(target)?;
+                for (_, arg) in args {
+                    self.compile_expr(arg)?;
+                }
+                self.emit(Instruction::MakeMessage {
+                    variant: msg_variant.clone(),
+                    fields: args.iter().map(|(name, _)| name.clone()).collect(),
+                });
+                self.emit(Instruction::Send);
+            }
+            StmtKind::Effect { name, args } if self.functions.contains_key(name) => {
+                let returns_value = self.compile_call(name, args)?;
+                if returns_value {
+                    self.emit(Instruction::Pop);
+                }
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.compile_expr(value)?;
+                }
+                self.emit(Instruction::Return);
+            }
+            StmtKind::Effect { name, args } => {
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                self.emit(Instruction::Effect {
+                    name: name.clone(),
+                    arg_count: args.len(),
+                });
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                self.compile_expr(cond)?;
+                let to_else = self.emit(Instruction::JumpIfFalse(0));
+                self.compile_block(then_body)?;
+                let to_end = self.emit(Instruction::Jump(0));
+                self.patch_jump(to_else);
+                self.compile_block(else_body)?;
+                self.patch_jump(to_end);
+            }
+            StmtKind::While { cond, body } => {
+                let start = self.instructions.len();
+                self.compile_expr(cond)?;
+                let to_exit = self.emit(Instruction::JumpIfFalse(0));
+                let jumps = self.compile_loop_body(body, Some(start))?;
+                self.emit(Instruction::Jump(start));
+                self.patch_jump(to_exit);
+                for at in jumps.breaks {
+                    self.patch_jump(at);
+                }
+            }
+            StmtKind::For {
+                var,
+                iter: ForIter::List(list),
+                body,
+            } => {
+                // Walk a snapshot of the list by index
+                self.push_scope();
+                self.compile_expr(list)?;
+                let items = self.declare("$items");
+                self.emit(Instruction::StoreLocal(items));
+                self.emit(Instruction::LoadConst(Value::Int(0)));
+                let index = self.declare("$index");
+                self.emit(Instruction::StoreLocal(index));
+
+                let top = self.emit(Instruction::LoadLocal(index));
+                self.emit(Instruction::LoadLocal(items));
+                self.emit(Instruction::CallMethod {
+                    name: "len".to_string(),
+                    arg_count: 0,
+                });
+                self.emit(Instruction::BinOp(BinOp::Lt));
+                let to_exit = self.emit(Instruction::JumpIfFalse(0));
+                self.emit(Instruction::LoadLocal(items));
+                self.emit(Instruction::LoadLocal(index));
+                self.emit(Instruction::Index);
+                let slot = self.declare(var);
+                self.emit(Instruction::StoreLocal(slot));
+
+                let jumps = self.compile_loop_body(body, None)?;
+                self.emit(Instruction::LoadLocal(index));
+                self.emit(Instruction::LoadConst(Value::Int(1)));
+                self.emit(Instruction::BinOp(BinOp::Add));
+                self.emit(Instruction::StoreLocal(index));
+                self.emit(Instruction::Jump(top));
+                self.patch_jump(to_exit);
+                for at in jumps.breaks {
+                    self.patch_jump(at);
+                }
+                self.pop_scope();
+            }
+            StmtKind::For {
+                var,
+                iter: ForIter::Range { start, end },
+                body,
+            } => {
+                // A hidden counter drives the loop so the body may reassign `var`
+                self.push_scope();
+                self.compile_expr(start)?;
+                let counter = self.declare("$counter");
+                self.emit(Instruction::StoreLocal(counter));
+                self.compile_expr(end)?;
+                let limit = self.declare("$limit");
+                self.emit(Instruction::StoreLocal(limit));
+
+                let top = self.emit(Instruction::LoadLocal(counter));
+                self.emit(Instruction::LoadLocal(limit));
+                self.emit(Instruction::BinOp(BinOp::Lt));
+                let to_exit = self.emit(Instruction::JumpIfFalse(0));
+                self.emit(Instruction::LoadLocal(counter));
+                let slot = self.declare(var);
+                self.emit(Instruction::StoreLocal(slot));
+
+                let jumps = self.compile_loop_body(body, None)?;
+                self.emit(Instruction::LoadLocal(counter));
+                self.emit(Instruction::LoadConst(Value::Int(1)));
+                self.emit(Instruction::BinOp(BinOp::Add));
+                self.emit(Instruction::StoreLocal(counter));
+                self.emit(Instruction::Jump(top));
+                self.patch_jump(to_exit);
+                for at in jumps.breaks {
+                    self.patch_jump(at);
+                }
+                self.pop_scope();
+            }
+            StmtKind::Break => {
+                let at = self.emit(Instruction::Jump(0));
+                match self.loops.last_mut() {
+                    Some(jumps) => jumps.breaks.push(at),
+                    None => anyhow::bail!("`break` outside of a loop"),
+                }
+            }
+            StmtKind::Continue => {
+                let at = self.emit(Instruction::Jump(0));
+                match self.loops.last_mut() {
+                    Some(jumps) => jumps.continues.push(at),
+                    None => anyhow::bail!("`continue` outside of a loop"),
+                }
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                // The scrutinee stays on the stack while arms are tested
+                self.compile_expr(scrutinee)?;
+                let mut to_end = Vec::new();
+                for arm in arms {
+                    self.push_scope();
+                    let to_next = match &arm.pattern {
+                        Pattern::Variant { name, bindings } => {
+                            self.emit(Instruction::Dup);
+                            self.emit(Instruction::IsVariant(name.clone()));
+                            let to_next = self.emit(Instruction::JumpIfFalse(0));
+                            for binding in bindings {
+                                self.emit(Instruction::Dup);
+                                self.emit(Instruction::FieldAccess(binding.clone()));
+                                let slot = self.declare(binding);
+                                self.emit(Instruction::StoreLocal(slot));
+                            }
+                            Some(to_next)
+                        }
+                        Pattern::Some(binding) => {
+                            self.emit(Instruction::Dup);
+                            self.emit(Instruction::IsSome);
+                            let to_next = self.emit(Instruction::JumpIfFalse(0));
+                            self.emit(Instruction::Dup);
+                            self.emit(Instruction::Unwrap);
+                            let slot = self.declare(binding);
+                            self.emit(Instruction::StoreLocal(slot));
+                            Some(to_next)
+                        }
+                        Pattern::None => {
+                            self.emit(Instruction::Dup);
+                            self.emit(Instruction::IsSome);
+                            Some(self.emit(Instruction::JumpIfTrue(0)))
+                        }
+                        Pattern::Wildcard => None,
+                    };
+                    self.emit(Instruction::Pop);
+                    self.compile_block(&arm.body)?;
+                    self.pop_scope();
+                    to_end.push(self.emit(Instruction::Jump(0)));
+                    if let Some(to_next) = to_next {
+                        self.patch_jump(to_next);
+                    }
+                }
+                // No arm matched
+                self.emit(Instruction::Pop);
+                for at in to_end {
+                    self.patch_jump(at);
+                }
+            }
+            StmtKind::Expr(expr) => {
+                self.compile_expr(expr)?;
+                // Updating methods leave the new collection to write back
+                let place = match &expr.kind {
+                    ExprKind::MethodCall { receiver, method, .. } if is_mutating(method) => Some(receiver),
+                    _ => None,
+                };
+                let instruction = match place.map(|receiver| &receiver.kind) {
+                    None => Instruction::Pop,
+                    Some(ExprKind::StateField(field)) => Instruction::StoreState(field.clone()),
+                    Some(ExprKind::Var(name)) => match self.resolve(name) {
+                        Some(slot) => Instruction::StoreLocal(slot),
+                        None => anyhow::bail!("Undefined variable: {}", name),
+                    },
+                    Some(_) => anyhow::bail!("Cannot update a temporary value"),
+                };
+                self.emit(instruction);
+            }
         }
-        Expr::FieldAccess { obj, field } => {
-            compile_expr(obj, instructions)?;
-            instructions.push(Instruction::FieldAccess(field.clone()));
+        Ok(())
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> Result<()> {
+        match &expr.kind {
+            // Locals shadow constructor parameters, which shadow agent names
+            ExprKind::Var(name) => match (self.resolve(name), self.params.iter().position(|p| p == name)) {
+                (Some(slot), _) => {
+                    self.emit(Instruction::LoadLocal(slot));
+                }
+                (None, Some(index)) => {
+                    self.emit(Instruction::LoadParam(index));
+                }
+                (None, None) => {
+                    self.emit(Instruction::LoadAgent(name.clone()));
+                }
+            },
+            ExprKind::StateField(field) => {
+                self.emit(Instruction::LoadState(field.clone()));
+            }
+            ExprKind::Int(n) => {
+                self.emit(Instruction::LoadConst(Value::Int(*n)));
+            }
+            ExprKind::Float(n) => {
+                self.emit(Instruction::LoadConst(Value::Float(*n)));
+            }
+            ExprKind::Duration(d) => {
+                self.emit(Instruction::LoadConst(Value::Duration(*d)));
+            }
+            ExprKind::Str(s) => {
+                self.emit(Instruction::LoadConst(Value::Str(s.clone())));
+            }
+            ExprKind::Interpolate(parts) => {
+                // `"a ${x} b"` becomes `"a " + str(x) + " b"`
+                self.emit(Instruction::LoadConst(Value::Str(String::new())));
+                for part in parts {
+                    match part {
+                        StrPart::Lit(text) => {
+                            self.emit(Instruction::LoadConst(Value::Str(text.clone())));
+                        }
+                        StrPart::Expr(expr) => {
+                            self.compile_expr(expr)?;
+                            self.emit(Instruction::ToStr);
+                        }
+                    }
+                    self.emit(Instruction::BinOp(BinOp::Add));
+                }
+            }
+            ExprKind::Bool(b) => {
+                self.emit(Instruction::LoadConst(Value::Bool(*b)));
+            }
+            ExprKind::BinOp {
+                op: op @ (BinOp::And | BinOp::Or),
+                left,
+                right,
+            } => {
+                // `a && b` skips `b` when `a` is false; `a || b` when `a` is true
+                let short_circuit = matches!(op, BinOp::Or);
+                self.compile_expr(left)?;
+                let skip = self.emit(if short_circuit {
+                    Instruction::JumpIfTrue(0)
+                } else {
+                    Instruction::JumpIfFalse(0)
+                });
+                self.compile_expr(right)?;
+                let end = self.emit(Instruction::Jump(0));
+                self.patch_jump(skip);
+                self.emit(Instruction::LoadConst(Value::Bool(short_circuit)));
+                self.patch_jump(end);
+            }
+            ExprKind::BinOp { op, left, right } => {
+                self.compile_expr(left)?;
+                self.compile_expr(right)?;
+                self.emit(Instruction::BinOp(op.clone()));
+            }
+            ExprKind::Unary { op, operand } => {
+                self.compile_expr(operand)?;
+                self.emit(Instruction::UnaryOp(op.clone()));
+            }
+            ExprKind::FieldAccess { obj, field } => {
+                self.compile_expr(obj)?;
+                self.emit(Instruction::FieldAccess(field.clone()));
+            }
+            ExprKind::Construct {
+                type_name: _,
+                variant,
+                fields,
+            } => {
+                for (_, value) in fields {
+                    self.compile_expr(value)?;
+                }
+                self.emit(Instruction::MakeMessage {
+                    variant: variant.clone(),
+                    fields: fields.iter().map(|(name, _)| name.clone()).collect(),
+                });
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.compile_expr(item)?;
+                }
+                self.emit(Instruction::MakeList(items.len()));
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.compile_expr(key)?;
+                    self.compile_expr(value)?;
+                }
+                self.emit(Instruction::MakeMap(entries.len()));
+            }
+            ExprKind::Some(value) => {
+                self.compile_expr(value)?;
+                self.emit(Instruction::MakeSome);
+            }
+            ExprKind::None => {
+                self.emit(Instruction::LoadConst(Value::Option(None)));
+            }
+            ExprKind::Index { obj, index } => {
+                self.compile_expr(obj)?;
+                self.compile_expr(index)?;
+                self.emit(Instruction::Index);
+            }
+            ExprKind::Call { name, args } => {
+                self.compile_call(name, args)?;
+            }
+            ExprKind::Ask {
+                target,
+                msg_variant,
+                args,
+                timeout,
+            } => {
+                self.compile_expr(target)?;
+                for (_, arg) in args {
+                    self.compile_expr(arg)?;
+                }
+                self.compile_expr(timeout)?;
+                self.emit(Instruction::Ask {
+                    variant: msg_variant.clone(),
+                    fields: args.iter().map(|(name, _)| name.clone()).collect(),
+                });
+            }
+            ExprKind::Schedule {
+                delay,
+                repeat,
+                target,
+                msg_variant,
+                args,
+            } => {
+                self.compile_expr(target)?;
+                for (_, arg) in args {
+                    self.compile_expr(arg)?;
+                }
+                self.compile_expr(delay)?;
+                self.emit(Instruction::Schedule {
+                    variant: msg_variant.clone(),
+                    fields: args.iter().map(|(name, _)| name.clone()).collect(),
+                    repeat: *repeat,
+                });
+            }
+            ExprKind::SelfRef => {
+                self.emit(Instruction::LoadSelf);
+            }
+            ExprKind::Spawn { agent, type_args: _, args } => {
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                self.emit(Instruction::Spawn {
+                    agent: agent.clone(),
+                    arg_count: args.len(),
+                });
+            }
+            ExprKind::MethodCall { receiver, method, args } => {
+                self.compile_expr(receiver)?;
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                self.emit(Instruction::CallMethod {
+                    name: method.clone(),
+                    arg_count: args.len(),
+                });
+            }
         }
+        Ok(())
     }
-    Ok(())
 }
 
-fn eval_const_expr(expr: &Expr) -> Result<Value> {
-    match expr {
-        Expr::Int(n) => Ok(Value::Int(*n)),
-        Expr::Str(s) => Ok(Value::Str(s.clone())),
-        Expr::Bool(b) => Ok(Value::Bool(*b)),
-        _ => anyhow::bail!("Non-constant expression in state initialization"),
-    }
+/// Methods that produce an updated copy of their receiver
+fn is_mutating(method: &str) -> bool {
+    matches!(method, "push" | "insert")
 }
@@ -0,0 +1,255 @@
+// Compiler diagnostics with rustc-style rendering
+use crate::ast::Span;
+use lalrpop_util::ParseError;
+use std::fmt;
+
+/// A span with an explanatory message
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A compile error pointing at the source that caused it
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            primary: Label {
+                span,
+                message: String::new(),
+            },
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Set the message shown under the primary caret
+    pub fn with_primary(mut self, message: impl Into<String>) -> Self {
+        self.primary.message = message.into();
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Convert a parser error; its locations, unlike those of the spans the
+    /// grammar builds, are relative to the parsed text, which is at `base`
+    pub fn from_parse_error<T: fmt::Display>(base: usize, err: ParseError<usize, T, Diagnostic>) -> Self {
+        match err.map_location(|location| base + location) {
+            ParseError::InvalidToken { location } => {
+                Self::error("invalid token", Span::new(location, location + 1))
+            }
+            ParseError::UnrecognizedEof { location, expected } => {
+                Self::error("unexpected end of file", Span::new(location, location))
+                    .with_note(expected_note(&expected))
+            }
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => Self::error(format!("unexpected token `{}`", token), Span::new(start, end))
+                .with_note(expected_note(&expected)),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => Self::error(format!("extra token `{}`", token), Span::new(start, end)),
+            ParseError::User { error } => error,
+        }
+    }
+
+    /// Render as `error: ...` with file:line:col, source snippets and carets;
+    /// labels in other files than the primary one get their own `:::` snippet
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut labels: Vec<(&Label, char)> = vec![(&self.primary, '^')];
+        labels.extend(self.secondary.iter().map(|label| (label, '-')));
+        labels.sort_by_key(|(label, _)| label.span.start);
+
+        let primary_file = sources.file_of(self.primary.span.start);
+        let gutter = labels
+            .iter()
+            .map(|(label, _)| {
+                let file = sources.file_of(label.span.start);
+                line_col(&file.source, label.span.start - file.base).0.to_string().len()
+            })
+            .max()
+            .unwrap_or(1);
+        let pad = " ".repeat(gutter);
+
+        let mut out = format!("error: {}\n", self.message);
+        let (line, col) = line_col(&primary_file.source, self.primary.span.start - primary_file.base);
+        out.push_str(&format!("{}--> {}:{}:{}\n", pad, primary_file.path, line, col));
+        out.push_str(&format!("{} |\n", pad));
+
+        let (local, elsewhere): (Vec<_>, Vec<_>) = labels
+            .into_iter()
+            .partition(|(label, _)| sources.file_of(label.span.start).base == primary_file.base);
+        render_snippet(&mut out, &local, primary_file, gutter);
+        for (label, marker) in elsewhere {
+            let file = sources.file_of(label.span.start);
+            let (line, col) = line_col(&file.source, label.span.start - file.base);
+            out.push_str(&format!("{}::: {}:{}:{}\n", pad, file.path, line, col));
+            out.push_str(&format!("{} |\n", pad));
+            render_snippet(&mut out, &[(label, marker)], file, gutter);
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", pad, note));
+        }
+
+        out
+    }
+}
+
+/// Every file of a project, each placed at its own offset so that a `Span`
+/// can point into any of them
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: String,
+    pub source: String,
+    /// Offset of the file's first byte in span coordinates
+    pub base: usize,
+}
+
+impl SourceMap {
+    /// Add a file, returning the base its spans should be offset by
+    pub fn add(&mut self, path: impl Into<String>, source: impl Into<String>) -> usize {
+        // Leave a gap so an end-of-file span never lands in the next file
+        let base = self.files.last().map_or(0, |f| f.base + f.source.len() + 1);
+        self.files.push(SourceFile {
+            path: path.into(),
+            source: source.into(),
+            base,
+        });
+        base
+    }
+
+    /// The file a span offset falls in
+    pub fn file_of(&self, offset: usize) -> &SourceFile {
+        self.files
+            .iter()
+            .rev()
+            .find(|file| file.base <= offset)
+            .expect("a source map holds at least one file")
+    }
+}
+
+/// Print the lines of `file` under the labels, with carets beneath each
+fn render_snippet(out: &mut String, labels: &[(&Label, char)], file: &SourceFile, gutter: usize) {
+    let pad = " ".repeat(gutter);
+    let source = file.source.as_str();
+    let mut prev_line = None;
+    for &(label, marker) in labels {
+        let (start, end) = (label.span.start - file.base, label.span.end - file.base);
+        let (line, col) = line_col(source, start);
+        if prev_line.is_some_and(|prev| line > prev + 1) {
+            out.push_str("...\n");
+        }
+        let repeated_line = prev_line == Some(line);
+        prev_line = Some(line);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+
+        // Underline up to the end of the span or its first line
+        let line_len = text.chars().count();
+        let span_len = source
+            .get(start..end)
+            .map(|s| s.lines().next().unwrap_or("").chars().count())
+            .unwrap_or(0);
+        let width = span_len.clamp(1, (line_len + 1).saturating_sub(col).max(1));
+
+        if !repeated_line {
+            out.push_str(&format!("{:>gutter$} | {}\n", line, text));
+        }
+        out.push_str(&format!(
+            "{} | {}{}",
+            pad,
+            " ".repeat(col - 1),
+            marker.to_string().repeat(width)
+        ));
+        if !label.message.is_empty() {
+            out.push_str(&format!(" {}", label.message));
+        }
+        out.push('\n');
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+fn expected_note(expected: &[String]) -> String {
+    format!("expected one of {}", expected.join(", "))
+}
+
+/// 1-based line and column of a byte offset
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let col = source[line_start..offset].chars().count() + 1;
+    (line, col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_points_at_span() {
+        let source = "agent A {\n  x = y;\n}\n";
+        let start = source.find('y').unwrap();
+        let diag = Diagnostic::error("undefined variable `y`", Span::new(start, start + 1))
+            .with_primary("not found in this scope");
+
+        let mut sources = SourceMap::default();
+        sources.add("a.agent", source);
+        let rendered = diag.render(&sources);
+        assert!(rendered.starts_with("error: undefined variable `y`\n"));
+        assert!(rendered.contains("--> a.agent:2:7"));
+        assert!(rendered.contains("2 |   x = y;\n  |       ^ not found in this scope"));
+    }
+
+    #[test]
+    fn test_spans_end_at_last_token() {
+        let source = "type M { Ping { n: Int , to: Ref[M] } }
+                      fn f(m: M ) { if true { } }
+                      agent A : M // inferred otherwise
+                      { state { } on Ping { n } -> { } }
+                      agent B[T] { state { } on Ping { } -> { } }";
+        let program = crate::grammar::ProgramParser::new().parse(0, source).unwrap();
+        let text = |span: Span| &source[span.start..span.end];
+        assert_eq!(text(program.types[0].variants[0].fields[0].span), "n: Int");
+        let function = &program.functions[0];
+        assert_eq!(text(function.span), "fn f(m: M )");
+        assert_eq!(text(function.params[0].span), "m: M");
+        assert_eq!(text(function.body[0].span), "if true { }");
+        assert_eq!(text(program.agents[0].span), "agent A : M");
+        assert_eq!(text(program.agents[1].span), "agent B[T]");
+    }
+}
@@ -1,92 +1,690 @@
 // Bytecode interpreter
 use crate::bytecode::*;
-use agentr::{Effect, EffectContext};
+use crate::ast::REPLY_FIELD;
+use crate::ast::Strategy;
+use crate::modules::is_imported;
+use agentr::{
+    ActorRef, ActorSystem, Capability, ChildSpec, Effect, EffectContext, Lifecycle, Message, ReplyTo, Supervisor,
+    TimerHandle,
+};
 use anyhow::Result;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch, Notify, RwLock};
 
-pub async fn execute(program: BytecodeProgram) -> Result<()> {
+/// Mailbox capacity of every spawned agent
+const MAILBOX_SIZE: usize = 64;
+
+/// Nested function calls one handler invocation may make before it is aborted
+const MAX_CALL_DEPTH: usize = 256;
+
+impl Message for Value {}
+
+/// What agent mailboxes carry: a message and how its delivery is counted
+#[derive(Debug, Clone)]
+struct Envelope {
+    msg: Value,
+    source: Source,
+}
+
+impl Message for Envelope {}
+
+/// How a delivery is accounted for in `Runtime::pending`
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    /// `send` or `ask`, counted when sent
+    Send,
+    /// A `schedule` timer firing; the timer's pending slot passes to its message
+    Timer(u64),
+    /// A tick of an `every` timer, which holds one slot until cancelled
+    Tick,
+}
+
+/// Interpreter settings
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Loop iterations (backward jumps) one handler invocation may take before it is aborted
+    pub iteration_budget: u64,
+    /// Also collects every logged line, in the order they are logged
+    pub log: Option<Arc<Mutex<Vec<String>>>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            iteration_budget: 100_000,
+            log: None,
+        }
+    }
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// One running agent: its address, constructor arguments and state
+struct Instance {
+    id: u64,
+    params: Vec<Value>,
+    state: RwLock<HashMap<String, Value>>,
+}
+
+/// Shared runtime state visible to every running agent
+struct Runtime {
+    config: Config,
+    effect_ctx: Arc<EffectContext>,
+    log_cap: Capability,
+    functions: Vec<BytecodeFunction>,
+    /// Every agent definition, for `Spawn`
+    agents: HashMap<String, Arc<BytecodeAgent>>,
+    /// Every agent started so far; stopped ones keep their id without a ref
+    actors: RwLock<HashMap<u64, Option<ActorRef<Envelope>>>>,
+    /// Agents started at startup, addressable by name
+    names: RwLock<HashMap<String, u64>>,
+    /// Set once every agent started at startup is addressable by name
+    running: watch::Sender<bool>,
+    next_id: AtomicU64,
+    /// Every unsupervised actor started so far, stopped once the program is done
+    system: ActorSystem,
+    /// Temporary refs of the `ask`s waiting for a reply
+    replies: Mutex<HashMap<u64, ReplyTo<Value>>>,
+    /// Timers that may still deliver a message
+    timers: Mutex<HashMap<u64, TimerHandle>>,
+    /// Messages sent but not yet fully handled, plus armed timers
+    pending: AtomicUsize,
+    idle: Notify,
+}
+
+impl Runtime {
+    /// Start a new instance of an agent, returning its id once its state is
+    /// initialized. Boxed because state initializers may spawn agents too.
+    fn spawn(self: &Arc<Self>, agent: Arc<BytecodeAgent>, params: Vec<Value>) -> BoxFuture<'_, Result<u64>> {
+        Box::pin(async move {
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            self.start(id, agent, params).await?;
+            Ok(id)
+        })
+    }
+
+    /// Start an instance under an id allocated beforehand
+    async fn start(self: &Arc<Self>, id: u64, agent: Arc<BytecodeAgent>, params: Vec<Value>) -> Result<()> {
+        let instance = self.init(id, &agent, params).await?;
+
+        // Held until the ref is in place, so the start hook can already send to itself
+        let mut actors = self.actors.write().await;
+        let lifecycle = lifecycle(&agent, &instance, self, Pending::start(self));
+        let actor_ref = self.system.spawn_with(MAILBOX_SIZE, lifecycle, agent_handler(agent, instance, self.clone()));
+        actors.insert(id, Some(actor_ref));
+        Ok(())
+    }
+
+    /// Create an instance of an agent with its state initialized
+    async fn init(self: &Arc<Self>, id: u64, agent: &BytecodeAgent, params: Vec<Value>) -> Result<Arc<Instance>> {
+        let instance = Arc::new(Instance {
+            id,
+            params,
+            state: RwLock::new(HashMap::new()),
+        });
+        let locals = vec![None; agent.init.num_locals];
+        execute_handler(&agent.init, &instance, self, locals).await?;
+        Ok(instance)
+    }
+
+    /// Build the runtime supervisor for a `supervisor` declaration. Its agents
+    /// keep their ref across restarts, each of which starts from fresh state;
+    /// they are added to `started` for registering by name.
+    fn supervisor(
+        self: &Arc<Self>,
+        def: &BytecodeSupervisor,
+        defs: &HashMap<&str, &BytecodeSupervisor>,
+        started: &mut Vec<(String, u64, ActorRef<Envelope>)>,
+    ) -> Supervisor {
+        let strategy = match def.strategy {
+            Strategy::OneForOne => agentr::Strategy::OneForOne,
+            Strategy::OneForAll => agentr::Strategy::OneForAll,
+            Strategy::RestForOne => agentr::Strategy::RestForOne,
+        };
+        let mut supervisor = Supervisor::new(def.name.clone(), strategy);
+        if let Some(limit) = def.max_restarts {
+            supervisor = supervisor.max_restarts(limit.count, limit.window);
+        }
+        let name = def.name.clone();
+        supervisor = supervisor.on_failure(move |child, error| {
+            eprintln!("Supervisor {}: child {} failed: {}; restarting", name, child, error)
+        });
+
+        for child in &def.children {
+            if let Some(agent) = self.agents.get(child) {
+                let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+                let (agent, runtime) = (agent.clone(), self.clone());
+                let (spec, actor_ref) = ChildSpec::actor_with(child.clone(), MAILBOX_SIZE, move || {
+                    let (agent, runtime) = (agent.clone(), runtime.clone());
+                    let starting = Pending::start(&runtime);
+                    async move {
+                        let instance = runtime.init(id, &agent, Vec::new()).await?;
+                        let lifecycle = lifecycle(&agent, &instance, &runtime, starting);
+                        Ok((lifecycle, agent_handler(agent, instance, runtime)))
+                    }
+                });
+                started.push((child.clone(), id, actor_ref));
+                supervisor = supervisor.child(spec);
+            } else if let Some(nested) = defs.get(child.as_str()) {
+                supervisor = supervisor.child(ChildSpec::supervisor(self.supervisor(nested, defs, started)));
+            }
+        }
+        supervisor
+    }
+
+    async fn lookup(&self, name: &str) -> Option<u64> {
+        self.names.read().await.get(name).copied()
+    }
+
+    /// The mailbox of an agent, or `None` if `target` is not an agent's ref
+    async fn mailbox(&self, target: u64) -> Result<Option<ActorRef<Envelope>>> {
+        match self.actors.read().await.get(&target) {
+            Some(Some(actor_ref)) => Ok(Some(actor_ref.clone())),
+            Some(None) => Err(stopped(target)),
+            None => Ok(None),
+        }
+    }
+
+    async fn send(&self, target: u64, msg: Value) -> Result<()> {
+        // A reply completes its `ask` rather than queueing; the asker may
+        // have stopped waiting already
+        if let Some(reply) = self.replies.lock().unwrap().remove(&target) {
+            let _ = reply.send(msg);
+            return Ok(());
+        }
+        let Some(actor_ref) = self.mailbox(target).await? else {
+            // Only the temporary ref of a finished `ask` has nothing behind it
+            return Ok(());
+        };
+
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let envelope = Envelope {
+            msg,
+            source: Source::Send,
+        };
+        if actor_ref.send(envelope).await.is_err() {
+            self.finish();
+            return Err(stopped(target));
+        }
+        Ok(())
+    }
+
+    /// Send a request with a fresh ref as its `replyTo` field, and wait up to
+    /// `timeout` for whatever is sent to that ref
+    async fn ask(
+        &self,
+        target: u64,
+        variant: &str,
+        mut fields: Vec<(String, Value)>,
+        timeout: Duration,
+    ) -> Result<Option<Value>> {
+        let Some(actor_ref) = self.mailbox(target).await? else {
+            return Ok(None);
+        };
+        let reply_id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let request = |reply: ReplyTo<Value>| {
+            self.replies.lock().unwrap().insert(reply_id, reply);
+            fields.push((REPLY_FIELD.to_string(), Value::Ref(reply_id)));
+            let msg = Value::Message {
+                variant: variant.to_string(),
+                fields,
+            };
+            Envelope {
+                msg,
+                source: Source::Send,
+            }
+        };
+
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let result = actor_ref.ask(request, timeout).await;
+        if result.is_err() {
+            self.finish();
+        }
+        self.replies.lock().unwrap().remove(&reply_id);
+        result.map_err(|_| stopped(target))
+    }
+
+    /// Arm a timer delivering `msg` to `target` after `delay`, or every
+    /// `delay` if `repeat`. Until it fires or is cancelled it counts as
+    /// pending, so the program stays alive for it.
+    async fn schedule(&self, target: u64, msg: Value, delay: Duration, repeat: bool) -> Result<u64> {
+        if repeat && delay.is_zero() {
+            anyhow::bail!("Cannot repeat a message every 0s");
+        }
+        let Some(actor_ref) = self.mailbox(target).await? else {
+            anyhow::bail!("Cannot schedule a message for ref #{}, which is not an agent", target);
+        };
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        // Registered before arming, so a quick delivery finds the entry to remove
+        let mut timers = self.timers.lock().unwrap();
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let handle = if repeat {
+            agentr::send_every(actor_ref, delay, Envelope { msg, source: Source::Tick })
+        } else {
+            agentr::send_after(actor_ref, delay, Envelope { msg, source: Source::Timer(id) })
+        };
+        timers.insert(id, handle);
+        Ok(id)
+    }
+
+    /// Stop a timer, returning whether it would still have delivered a message
+    fn cancel(&self, timer: u64) -> bool {
+        let Some(handle) = self.timers.lock().unwrap().remove(&timer) else {
+            return false;
+        };
+        let cancelled = handle.cancel();
+        if cancelled {
+            self.finish();
+        }
+        cancelled
+    }
+
+    /// Account for a handled message
+    fn delivered(&self, source: Source) {
+        match source {
+            Source::Send => self.finish(),
+            Source::Timer(id) => {
+                self.timers.lock().unwrap().remove(&id);
+                self.finish();
+            }
+            Source::Tick => {}
+        }
+    }
+
+    /// Mark one in-flight message as handled; see also `Pending`
+    fn finish(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.idle.notify_waiters();
+        }
+    }
+
+    /// Wait until no message is queued or being handled
+    async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            if self.pending.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+pub async fn execute(program: BytecodeProgram, config: Config) -> Result<()> {
     let effect_ctx = Arc::new(EffectContext::new());
 
     // Grant log capability for all agents
     let log_cap = effect_ctx.grant(Effect::Log).await;
 
-    // Execute first agent for demo (full version would spawn all agents)
-    if let Some(agent) = program.agents.first() {
-        execute_agent(agent, effect_ctx.clone(), log_cap).await?;
+    let agents: Vec<Arc<BytecodeAgent>> = program.agents.into_iter().map(Arc::new).collect();
+    let supervisors: HashMap<&str, &BytecodeSupervisor> =
+        program.supervisors.iter().map(|s| (s.name.as_str(), s)).collect();
+    let supervised: HashSet<&str> = program.supervisors.iter().flat_map(|s| &s.children).map(String::as_str).collect();
+    let runtime = Arc::new(Runtime {
+        config,
+        effect_ctx,
+        log_cap,
+        functions: program.functions,
+        agents: agents.iter().map(|agent| (agent.name.clone(), agent.clone())).collect(),
+        actors: RwLock::new(HashMap::new()),
+        names: RwLock::new(HashMap::new()),
+        running: watch::Sender::new(false),
+        next_id: AtomicU64::new(0),
+        system: ActorSystem::new(),
+        replies: Mutex::new(HashMap::new()),
+        timers: Mutex::new(HashMap::new()),
+        pending: AtomicUsize::new(0),
+        idle: Notify::new(),
+    });
+
+    // Only the root file's agents and supervisors start at startup; imported
+    // ones start when spawned or supervised. Every agent started at startup
+    // is addressable by name before any state is initialized, so
+    // initializers may refer to agents declared later. Supervised agents get
+    // their ref right away; their state is initialized as their supervisor
+    // starts them.
+    let startup = |name: &str| !is_imported(name) && !supervised.contains(name);
+    let mut roots = Vec::new();
+    for def in program.supervisors.iter().filter(|s| startup(&s.name)) {
+        let mut started = Vec::new();
+        let supervisor = runtime.supervisor(def, &supervisors, &mut started);
+        for (name, id, actor_ref) in started {
+            runtime.actors.write().await.insert(id, Some(actor_ref));
+            runtime.names.write().await.insert(name, id);
+        }
+        roots.push((def.name.clone(), supervisor));
+    }
+    // Those with constructor parameters only start through `spawn`
+    let mut unsupervised = Vec::new();
+    for agent in agents.into_iter().filter(|agent| startup(&agent.name)) {
+        if agent.params.is_empty() {
+            let id = runtime.next_id.fetch_add(1, Ordering::SeqCst);
+            runtime.names.write().await.insert(agent.name.clone(), id);
+            unsupervised.push((id, agent));
+        }
     }
 
-    Ok(())
+    // Spawn every agent before any message is delivered
+    for (id, agent) in unsupervised {
+        println!("Spawning agent: {}", agent.name);
+        runtime.start(id, agent, Vec::new()).await?;
+    }
+    let (failed, mut failures) = mpsc::unbounded_channel();
+    let mut watchers = Vec::new();
+    for (name, supervisor) in roots {
+        println!("Starting supervisor: {}", name);
+        let handle = supervisor.start();
+        let failed = failed.clone();
+        watchers.push(tokio::spawn(async move {
+            if let Err(e) = handle.join().await {
+                let _ = failed.send(e);
+            }
+        }));
+    }
+    drop(failed);
+    runtime.running.send_replace(true);
+
+    // A supervisor giving up ends the program, as nothing restarts it
+    tokio::select! {
+        _ = runtime.wait_idle() => {}
+        Some(e) = failures.recv() => return Err(e),
+    }
+
+    // Stop agents in reverse spawn order, so their stop hooks can still
+    // message those spawned earlier; dropping the remaining refs closes the
+    // mailboxes of supervised agents
+    runtime.system.shutdown().await?;
+    runtime.actors.write().await.values_mut().for_each(|actor_ref| *actor_ref = None);
+    for watcher in watchers {
+        watcher.await?;
+    }
+    match failures.try_recv() {
+        Ok(e) => Err(e),
+        Err(_) => Ok(()),
+    }
+}
+
+/// The error for messaging an agent that has stopped
+fn stopped(target: u64) -> anyhow::Error {
+    anyhow::anyhow!("Cannot message ref #{}, whose agent has stopped", target)
+}
+
+/// One unit of `Runtime::pending`, released when dropped: a message being
+/// handled, or an instance starting up. Work a supervisor abandons when it
+/// restarts an agent is released along with it.
+struct Pending {
+    runtime: Arc<Runtime>,
+    source: Source,
+}
+
+impl Pending {
+    /// Count an instance as busy until its `on start` hook has run
+    fn start(runtime: &Arc<Runtime>) -> Self {
+        runtime.pending.fetch_add(1, Ordering::SeqCst);
+        Self {
+            runtime: runtime.clone(),
+            source: Source::Send,
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.runtime.delivered(self.source);
+    }
 }
 
-async fn execute_agent(
+/// Run an instance's `on start` hook before its first message, its
+/// `on stop` hook once it stops and, when supervised, its `on restart` hook
+/// before `on start` on a restart; `starting` is released after `on start`
+fn lifecycle(agent: &Arc<BytecodeAgent>, instance: &Arc<Instance>, runtime: &Arc<Runtime>, starting: Pending) -> Lifecycle {
+    let (start_agent, start_instance, start_runtime) = (agent.clone(), instance.clone(), runtime.clone());
+    let lifecycle = Lifecycle::new().on_start(move || async move {
+        let _starting = starting;
+        // Hooks may address any agent started at startup by name
+        let _ = start_runtime.running.subscribe().wait_for(|running| *running).await;
+        run_hook(&start_agent.on_start, &start_instance, &start_runtime).await
+    });
+    let (stop_agent, stop_instance, stop_runtime) = (agent.clone(), instance.clone(), runtime.clone());
+    let lifecycle = lifecycle.on_stop(move || async move { run_hook(&stop_agent.on_stop, &stop_instance, &stop_runtime).await });
+    let (agent, instance, runtime) = (agent.clone(), instance.clone(), runtime.clone());
+    lifecycle.on_restart(move || async move { run_hook(&agent.on_restart, &instance, &runtime).await })
+}
+
+async fn run_hook(hook: &Option<BytecodeHandler>, instance: &Instance, runtime: &Arc<Runtime>) -> Result<()> {
+    match hook {
+        Some(hook) => {
+            let locals = vec![None; hook.num_locals];
+            execute_handler(hook, instance, runtime, locals).await
+        }
+        None => Ok(()),
+    }
+}
+
+/// The mailbox handler of one instance
+fn agent_handler(
+    agent: Arc<BytecodeAgent>,
+    instance: Arc<Instance>,
+    runtime: Arc<Runtime>,
+) -> impl Fn(Envelope) -> BoxFuture<'static, Result<()>> {
+    move |envelope: Envelope| {
+        let agent = agent.clone();
+        let instance = instance.clone();
+        let runtime = runtime.clone();
+
+        Box::pin(async move {
+            let _delivery = Pending {
+                runtime: runtime.clone(),
+                source: envelope.source,
+            };
+            dispatch(&agent, &instance, &runtime, envelope.msg).await
+        })
+    }
+}
+
+/// Select the handler for the message variant and run it
+async fn dispatch(
     agent: &BytecodeAgent,
-    effect_ctx: Arc<EffectContext>,
-    log_cap: agentr::Capability,
+    instance: &Instance,
+    runtime: &Arc<Runtime>,
+    msg: Value,
 ) -> Result<()> {
-    println!("Executing agent: {}", agent.name);
+    let Value::Message { variant, fields } = msg else {
+        anyhow::bail!("Agent {} received a non-message value", agent.name);
+    };
 
-    // Initialize state
-    let state = Arc::new(RwLock::new(HashMap::new()));
-    for (name, value) in &agent.state_init {
-        state.write().await.insert(name.clone(), value.clone());
-    }
+    let handler = agent
+        .handlers
+        .iter()
+        .find(|h| h.variant == variant)
+        .ok_or_else(|| anyhow::anyhow!("Agent {} has no handler for {}", agent.name, variant))?;
 
-    // For demo: execute first handler with synthetic message
-    if let Some(handler) = agent.handlers.first() {
-        println!("  Handler: {}", handler.variant);
-        execute_handler(
-            handler,
-            state.clone(),
-            effect_ctx.clone(),
-            log_cap.clone(),
-            &HashMap::new(),
-        )
-        .await?;
+    // Bind destructured params from the message fields by name into the first slots
+    let mut locals = vec![None; handler.num_locals];
+    for (slot, param) in handler.params.iter().enumerate() {
+        let value = fields
+            .iter()
+            .find(|(name, _)| name == param)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| anyhow::anyhow!("Message {} has no field {}", variant, param))?;
+        locals[slot] = Some(value);
     }
 
-    Ok(())
+    execute_handler(handler, instance, runtime, locals).await
+}
+
+/// A suspended caller, resumed when the callee returns
+struct Frame<'a> {
+    code: &'a [Instruction],
+    pc: usize,
+    locals: Vec<Option<Value>>,
+    /// Stack height when the frame was entered
+    stack_base: usize,
+    returns_value: bool,
 }
 
 async fn execute_handler(
     handler: &BytecodeHandler,
-    state: Arc<RwLock<HashMap<String, Value>>>,
-    effect_ctx: Arc<EffectContext>,
-    log_cap: agentr::Capability,
-    _params: &HashMap<String, Value>,
+    instance: &Instance,
+    runtime: &Arc<Runtime>,
+    mut locals: Vec<Option<Value>>,
 ) -> Result<()> {
     let mut stack: Vec<Value> = Vec::new();
+    let mut budget = runtime.config.iteration_budget;
 
-    for instr in &handler.instructions {
-        match instr {
-            Instruction::LoadVar(name) => {
-                let state_read = state.read().await;
-                if let Some(val) = state_read.get(name) {
-                    stack.push(val.clone());
+    // The running frame lives in these locals; callers wait in `calls`
+    let mut calls: Vec<Frame> = Vec::new();
+    let mut code: &[Instruction] = &handler.instructions;
+    let mut stack_base = 0;
+    let mut returns_value = false;
+
+    let mut pc = 0;
+    loop {
+        let Some(instr) = code.get(pc) else {
+            // Running off the end returns without a value
+            match calls.pop() {
+                Some(caller) => {
+                    stack.truncate(stack_base);
+                    (code, pc, locals, stack_base, returns_value) =
+                        (caller.code, caller.pc, caller.locals, caller.stack_base, caller.returns_value);
+                    continue;
                 }
+                None => break,
             }
-            Instruction::LoadConst(val) => {
-                stack.push(val.clone());
+        };
+        pc += 1;
+        match instr {
+            Instruction::LoadLocal(slot) => {
+                let value = locals[*slot]
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("Read of uninitialized local slot {}", slot))?;
+                stack.push(value);
+            }
+            Instruction::StoreLocal(slot) => {
+                locals[*slot] = stack.pop();
+            }
+            Instruction::LoadAgent(name) => {
+                let id = runtime
+                    .lookup(name)
+                    .await
+                    .ok_or_else(|| anyhow::anyhow!("Unknown agent: {}", name))?;
+                stack.push(Value::Ref(id));
             }
-            Instruction::Store(name) => {
+            Instruction::LoadSelf => {
+                stack.push(Value::Ref(instance.id));
+            }
+            Instruction::LoadParam(index) => {
+                stack.push(instance.params[*index].clone());
+            }
+            Instruction::Spawn { agent, arg_count } => {
+                let agent = runtime
+                    .agents
+                    .get(agent)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Unknown agent: {}", agent))?;
+                let args = stack.split_off(stack.len().saturating_sub(*arg_count));
+                let id = runtime.spawn(agent, args).await?;
+                stack.push(Value::Ref(id));
+            }
+            Instruction::LoadState(name) => {
+                let value = instance
+                    .state
+                    .read()
+                    .await
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Undefined state field: {}", name))?;
+                stack.push(value);
+            }
+            Instruction::StoreState(name) => {
                 if let Some(val) = stack.pop() {
-                    state.write().await.insert(name.clone(), val);
+                    instance.state.write().await.insert(name.clone(), val);
                 }
             }
+            Instruction::LoadConst(val) => {
+                stack.push(val.clone());
+            }
             Instruction::BinOp(op) => {
                 let right = stack.pop().unwrap();
                 let left = stack.pop().unwrap();
                 let result = eval_binop(op, &left, &right)?;
                 stack.push(result);
             }
-            Instruction::Send {
-                target_var: _,
-                variant: _,
-            } => {
-                // Simplified: just pop args from stack
-                // In full version, would actually send to target actor
-                println!("    [Send message - not implemented in demo]");
+            Instruction::UnaryOp(op) => {
+                let operand = stack.pop().unwrap();
+                stack.push(eval_unary(op, &operand)?);
+            }
+            Instruction::Jump(target) => {
+                if *target < pc {
+                    if budget == 0 {
+                        anyhow::bail!(
+                            "Handler {} exceeded its iteration budget of {}",
+                            handler.variant,
+                            runtime.config.iteration_budget
+                        );
+                    }
+                    budget -= 1;
+                }
+                pc = *target;
+            }
+            Instruction::JumpIfFalse(target) => {
+                if !pop_bool(&mut stack)? {
+                    pc = *target;
+                }
+            }
+            Instruction::JumpIfTrue(target) => {
+                if pop_bool(&mut stack)? {
+                    pc = *target;
+                }
+            }
+            Instruction::MakeMessage { variant, fields } => {
+                let values = stack.split_off(stack.len().saturating_sub(fields.len()));
+                stack.push(Value::Message {
+                    variant: variant.clone(),
+                    fields: fields.iter().cloned().zip(values).collect(),
+                });
+            }
+            Instruction::Send => {
+                let msg = stack.pop().unwrap();
+                match stack.pop() {
+                    Some(Value::Ref(target)) => runtime.send(target, msg).await?,
+                    other => anyhow::bail!("Cannot send to non-ref value {:?}", other),
+                }
+            }
+            Instruction::Ask { variant, fields } => {
+                let timeout = match stack.pop() {
+                    Some(Value::Duration(timeout)) => timeout,
+                    other => anyhow::bail!("Ask timeout must be a duration, got {:?}", other),
+                };
+                let values = stack.split_off(stack.len().saturating_sub(fields.len()));
+                let Some(Value::Ref(target)) = stack.pop() else {
+                    anyhow::bail!("Cannot ask a non-ref value");
+                };
+                let fields = fields.iter().cloned().zip(values).collect();
+                let reply = runtime.ask(target, variant, fields, timeout).await?;
+                stack.push(Value::Option(reply.map(Box::new)));
+            }
+            Instruction::Schedule { variant, fields, repeat } => {
+                let delay = match stack.pop() {
+                    Some(Value::Duration(delay)) => delay,
+                    other => anyhow::bail!("Schedule delay must be a duration, got {:?}", other),
+                };
+                let values = stack.split_off(stack.len().saturating_sub(fields.len()));
+                let Some(Value::Ref(target)) = stack.pop() else {
+                    anyhow::bail!("Cannot schedule a message for a non-ref value");
+                };
+                let msg = Value::Message {
+                    variant: variant.clone(),
+                    fields: fields.iter().cloned().zip(values).collect(),
+                };
+                let timer = runtime.schedule(target, msg, delay, *repeat).await?;
+                stack.push(Value::Timer(timer));
             }
             Instruction::Effect { name, arg_count } => {
                 let mut args = Vec::new();
@@ -98,11 +696,119 @@ async fn execute_handler(
                 args.reverse();
 
                 if name == "log" {
-                    effect_ctx.execute(&log_cap, &args).await?;
+                    let line = runtime.effect_ctx.execute(&runtime.log_cap, &args).await?;
+                    if let Some(log) = &runtime.config.log {
+                        log.lock().unwrap().push(line);
+                    }
                 }
             }
-            Instruction::FieldAccess(_field) => {
-                // Simplified: not implemented in demo
+            Instruction::Dup => {
+                let top = stack.last().cloned().unwrap();
+                stack.push(top);
+            }
+            Instruction::Pop => {
+                stack.pop();
+            }
+            Instruction::IsVariant(expected) => {
+                let is_variant = matches!(
+                    stack.pop(),
+                    Some(Value::Message { variant, .. }) if &variant == expected
+                );
+                stack.push(Value::Bool(is_variant));
+            }
+            Instruction::FieldAccess(field) => {
+                let value = match stack.pop() {
+                    Some(Value::Message { variant, fields }) => fields
+                        .into_iter()
+                        .find(|(name, _)| name == field)
+                        .map(|(_, value)| value)
+                        .ok_or_else(|| anyhow::anyhow!("{} has no field {}", variant, field))?,
+                    other => anyhow::bail!("Cannot access field {} on {:?}", field, other),
+                };
+                stack.push(value);
+            }
+            Instruction::MakeList(len) => {
+                let items = stack.split_off(stack.len().saturating_sub(*len));
+                stack.push(Value::List(items));
+            }
+            Instruction::MakeMap(len) => {
+                let flat = stack.split_off(stack.len().saturating_sub(len * 2));
+                let mut entries: Vec<(Value, Value)> = Vec::new();
+                let mut flat = flat.into_iter();
+                while let (Some(key), Some(value)) = (flat.next(), flat.next()) {
+                    map_insert(&mut entries, key, value);
+                }
+                stack.push(Value::Map(entries));
+            }
+            Instruction::MakeSome => {
+                let value = stack.pop().unwrap();
+                stack.push(Value::Option(Some(Box::new(value))));
+            }
+            Instruction::IsSome => {
+                let is_some = matches!(stack.pop(), Some(Value::Option(Some(_))));
+                stack.push(Value::Bool(is_some));
+            }
+            Instruction::Unwrap => match stack.pop() {
+                Some(Value::Option(Some(value))) => stack.push(*value),
+                other => anyhow::bail!("Cannot unwrap {:?}", other),
+            },
+            Instruction::Index => {
+                let index = stack.pop().unwrap();
+                let collection = stack.pop().unwrap();
+                let value = match (&collection, &index) {
+                    (Value::List(items), Value::Int(i)) => usize::try_from(*i)
+                        .ok()
+                        .and_then(|i| items.get(i))
+                        .ok_or_else(|| anyhow::anyhow!("Index {} out of bounds for list of length {}", i, items.len()))?,
+                    (Value::Map(entries), key) => map_get(entries, key)
+                        .ok_or_else(|| anyhow::anyhow!("Key {} not found in map", value_to_string(key)))?,
+                    _ => anyhow::bail!("Cannot index {:?} with {:?}", collection, index),
+                };
+                stack.push(value.clone());
+            }
+            Instruction::Call { function, arg_count } => {
+                if calls.len() >= MAX_CALL_DEPTH {
+                    anyhow::bail!("Handler {} exceeded the maximum call depth of {}", handler.variant, MAX_CALL_DEPTH);
+                }
+                let function = &runtime.functions[*function];
+                let args = stack.split_off(stack.len().saturating_sub(*arg_count));
+                let mut callee_locals = vec![None; function.num_locals];
+                for (slot, arg) in args.into_iter().enumerate() {
+                    callee_locals[slot] = Some(arg);
+                }
+                calls.push(Frame {
+                    code,
+                    pc,
+                    locals: std::mem::replace(&mut locals, callee_locals),
+                    stack_base,
+                    returns_value,
+                });
+                (code, pc, stack_base, returns_value) = (&function.instructions, 0, stack.len(), function.returns_value);
+            }
+            Instruction::Return => {
+                let Some(caller) = calls.pop() else {
+                    // `return` in a handler ends it
+                    break;
+                };
+                let result = if returns_value { stack.pop() } else { None };
+                stack.truncate(stack_base);
+                stack.extend(result);
+                (code, pc, locals, stack_base, returns_value) =
+                    (caller.code, caller.pc, caller.locals, caller.stack_base, caller.returns_value);
+            }
+            Instruction::ToStr => {
+                let value = stack.pop().unwrap();
+                stack.push(Value::Str(value_to_string(&value)));
+            }
+            Instruction::CallMethod { name, arg_count } => {
+                let args = stack.split_off(stack.len().saturating_sub(*arg_count));
+                let receiver = stack.pop().unwrap();
+                let result = match (receiver, name.as_str()) {
+                    // Timers live in the runtime, out of reach of the pure built-ins
+                    (Value::Timer(timer), "cancel") => Value::Bool(runtime.cancel(timer)),
+                    (receiver, _) => call_method(receiver, name, args)?,
+                };
+                stack.push(result);
             }
         }
     }
@@ -113,18 +819,483 @@ async fn execute_handler(
 fn eval_binop(op: &crate::ast::BinOp, left: &Value, right: &Value) -> Result<Value> {
     use crate::ast::BinOp::*;
     match (op, left, right) {
-        (Add, Value::Int(l), Value::Int(r)) => Ok(Value::Int(l + r)),
-        (Sub, Value::Int(l), Value::Int(r)) => Ok(Value::Int(l - r)),
-        (Mul, Value::Int(l), Value::Int(r)) => Ok(Value::Int(l * r)),
-        (Div, Value::Int(l), Value::Int(r)) => Ok(Value::Int(l / r)),
+        (Div | Mod, Value::Int(_), Value::Int(0)) => anyhow::bail!("Division by zero"),
+        (Add | Sub | Mul | Div | Mod, Value::Int(l), Value::Int(r)) => {
+            let result = match op {
+                Add => l.checked_add(*r),
+                Sub => l.checked_sub(*r),
+                Mul => l.checked_mul(*r),
+                Div => l.checked_div(*r),
+                _ => l.checked_rem(*r),
+            };
+            result
+                .map(Value::Int)
+                .ok_or_else(|| anyhow::anyhow!("Integer overflow in {} {} {}", l, op, r))
+        }
+        (Add, Value::Str(l), Value::Str(r)) => Ok(Value::Str(format!("{}{}", l, r))),
+        (Lt, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l < r)),
+        (Gt, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l > r)),
+        (Le, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l <= r)),
+        (Ge, Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l >= r)),
+        (Add | Sub, Value::Duration(l), Value::Duration(r)) => {
+            let result = if matches!(op, Add) { l.checked_add(*r) } else { l.checked_sub(*r) };
+            result
+                .map(Value::Duration)
+                .ok_or_else(|| anyhow::anyhow!("Duration out of range in {:?} {} {:?}", l, op, r))
+        }
+        (Div, Value::Duration(l), Value::Duration(r)) => Ok(Value::Float(l.as_secs_f64() / r.as_secs_f64())),
+        (Mul, Value::Duration(d), n) | (Mul, n, Value::Duration(d)) | (Div, Value::Duration(d), n) => {
+            let factor = as_float(n).ok_or_else(|| anyhow::anyhow!("Type error in binary operation"))?;
+            let factor = if matches!(op, Div) { 1.0 / factor } else { factor };
+            Duration::try_from_secs_f64(d.as_secs_f64() * factor)
+                .map(Value::Duration)
+                .map_err(|_| anyhow::anyhow!("Duration out of range in {:?} {} {}", d, op, value_to_string(n)))
+        }
+        (Lt, Value::Duration(l), Value::Duration(r)) => Ok(Value::Bool(l < r)),
+        (Gt, Value::Duration(l), Value::Duration(r)) => Ok(Value::Bool(l > r)),
+        (Le, Value::Duration(l), Value::Duration(r)) => Ok(Value::Bool(l <= r)),
+        (Ge, Value::Duration(l), Value::Duration(r)) => Ok(Value::Bool(l >= r)),
+        (Eq, l, r) if !is_mixed_numeric(l, r) => Ok(Value::Bool(l == r)),
+        (Ne, l, r) if !is_mixed_numeric(l, r) => Ok(Value::Bool(l != r)),
+        // Any remaining numeric mix involves a Float
+        (op, l, r) => match (as_float(l), as_float(r)) {
+            (Some(l), Some(r)) => eval_float_binop(op, l, r),
+            _ => anyhow::bail!("Type error in binary operation"),
+        },
+    }
+}
+
+fn eval_float_binop(op: &crate::ast::BinOp, l: f64, r: f64) -> Result<Value> {
+    use crate::ast::BinOp::*;
+    Ok(match op {
+        Add => Value::Float(l + r),
+        Sub => Value::Float(l - r),
+        Mul => Value::Float(l * r),
+        Div => Value::Float(l / r),
+        Mod => Value::Float(l % r),
+        Lt => Value::Bool(l < r),
+        Gt => Value::Bool(l > r),
+        Le => Value::Bool(l <= r),
+        Ge => Value::Bool(l >= r),
+        Eq => Value::Bool(l == r),
+        Ne => Value::Bool(l != r),
         _ => anyhow::bail!("Type error in binary operation"),
+    })
+}
+
+fn is_mixed_numeric(l: &Value, r: &Value) -> bool {
+    matches!((l, r), (Value::Int(_), Value::Float(_)) | (Value::Float(_), Value::Int(_)))
+}
+
+/// Numeric value widened to a float, for mixed Int/Float arithmetic
+fn as_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn eval_unary(op: &crate::ast::UnaryOp, operand: &Value) -> Result<Value> {
+    use crate::ast::UnaryOp::*;
+    match (op, operand) {
+        (Neg, Value::Int(n)) => n
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| anyhow::anyhow!("Integer overflow negating {}", n)),
+        (Neg, Value::Float(n)) => Ok(Value::Float(-n)),
+        (Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        _ => anyhow::bail!("Type error in unary operation"),
+    }
+}
+
+/// Built-in string and collection methods; `push` and `insert` return the updated receiver
+fn call_method(receiver: Value, name: &str, args: Vec<Value>) -> Result<Value> {
+    let mut args = args.into_iter();
+    let result = match (receiver, name) {
+        (Value::List(items), "len") => Value::Int(items.len() as i64),
+        (Value::Map(entries), "len") => Value::Int(entries.len() as i64),
+        (Value::List(mut items), "push") => {
+            items.extend(args.next());
+            Value::List(items)
+        }
+        (Value::List(mut items), "get") => {
+            let item = match args.next() {
+                Some(Value::Int(i)) if i >= 0 && (i as usize) < items.len() => Some(Box::new(items.swap_remove(i as usize))),
+                _ => None,
+            };
+            Value::Option(item)
+        }
+        (Value::List(items), "contains") => Value::Bool(args.next().is_some_and(|arg| items.contains(&arg))),
+        (Value::Map(entries), "get") => {
+            let value = args.next().and_then(|key| map_get(&entries, &key).cloned());
+            Value::Option(value.map(Box::new))
+        }
+        (Value::Map(mut entries), "insert") => {
+            if let (Some(key), Some(value)) = (args.next(), args.next()) {
+                map_insert(&mut entries, key, value);
+            }
+            Value::Map(entries)
+        }
+        (Value::Map(entries), "contains") => {
+            Value::Bool(args.next().is_some_and(|key| map_get(&entries, &key).is_some()))
+        }
+        (Value::Str(s), "len") => Value::Int(s.chars().count() as i64),
+        (Value::Str(s), "contains") => match args.next() {
+            Some(Value::Str(needle)) => Value::Bool(s.contains(needle.as_str())),
+            other => anyhow::bail!("contains expects a String, found {:?}", other),
+        },
+        (Value::Str(s), "split") => match args.next() {
+            Some(Value::Str(sep)) => Value::List(s.split(sep.as_str()).map(|part| Value::Str(part.to_string())).collect()),
+            other => anyhow::bail!("split expects a String, found {:?}", other),
+        },
+        (Value::Str(s), "to_upper") => Value::Str(s.to_uppercase()),
+        (Value::Str(s), "to_lower") => Value::Str(s.to_lowercase()),
+        (Value::Str(s), "trim") => Value::Str(s.trim().to_string()),
+        (Value::Str(s), "parse_int") => Value::Option(s.trim().parse().ok().map(|n| Box::new(Value::Int(n)))),
+        (Value::Option(value), "is_some") => Value::Bool(value.is_some()),
+        (Value::Option(value), "unwrap_or") => match value {
+            Some(value) => *value,
+            None => args.next().ok_or_else(|| anyhow::anyhow!("unwrap_or needs a default"))?,
+        },
+        (receiver, _) => anyhow::bail!("No method {} on {:?}", name, receiver),
+    };
+    Ok(result)
+}
+
+fn map_get<'a>(entries: &'a [(Value, Value)], key: &Value) -> Option<&'a Value> {
+    entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Insert or overwrite, keeping the original position of an existing key
+fn map_insert(entries: &mut Vec<(Value, Value)>, key: Value, value: Value) {
+    match entries.iter_mut().find(|(k, _)| *k == key) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key, value)),
+    }
+}
+
+fn pop_bool(stack: &mut Vec<Value>) -> Result<bool> {
+    match stack.pop() {
+        Some(Value::Bool(b)) => Ok(b),
+        other => anyhow::bail!("Expected a Bool condition, found {:?}", other),
     }
 }
 
 fn value_to_string(val: &Value) -> String {
     match val {
         Value::Int(n) => n.to_string(),
+        Value::Float(n) => format!("{:?}", n),
+        Value::Duration(d) => format!("{:?}", d),
         Value::Str(s) => s.clone(),
         Value::Bool(b) => b.to_string(),
+        Value::Message { variant, fields } => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value_to_string(value)))
+                .collect();
+            format!("{} {{ {} }}", variant, fields.join(", "))
+        }
+        Value::Ref(id) => format!("<ref #{}>", id),
+        Value::Timer(id) => format!("<timer #{}>", id),
+        Value::List(items) => {
+            let items: Vec<String> = items.iter().map(value_to_string).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", value_to_string(key), value_to_string(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Option(Some(value)) => format!("some({})", value_to_string(value)),
+        Value::Option(None) => "none".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::BinOp;
+
+    /// Compile and run a program, returning its log lines
+    async fn run(source: &str) -> Result<Vec<String>> {
+        run_with(source, Config::default()).await
+    }
+
+    async fn run_with(source: &str, config: Config) -> Result<Vec<String>> {
+        let program = crate::grammar::ProgramParser::new().parse(0, source).expect("parses");
+        run_program(program, config).await
+    }
+
+    async fn run_program(program: crate::ast::Program, mut config: Config) -> Result<Vec<String>> {
+        crate::typechecker::typecheck(&program).expect("typechecks");
+        let log = Arc::new(Mutex::new(Vec::new()));
+        config.log = Some(log.clone());
+        execute(crate::bytecode::compile(&program)?, config).await?;
+        let lines = log.lock().unwrap().clone();
+        Ok(lines)
+    }
+
+    /// Run `body` as the start hook of an agent its supervisor gives up on
+    /// at the first failure, returning the failure
+    async fn failure(body: &str) -> String {
+        let source = format!(
+            "agent A {{ state {{ n: Int = 0; }} on start -> {{ {} }} }}
+             fn deeper(n: Int) -> Int {{ return deeper(n + 1); }}
+             supervisor S {{ strategy: one_for_one, max_restarts: 0 within 1s, children {{ A }} }}",
+            body
+        );
+        let config = Config {
+            iteration_budget: 1000,
+            ..Config::default()
+        };
+        let error = run_with(&source, config).await.unwrap_err().to_string();
+        error.split("; child A failed: ").nth(1).unwrap_or(&error).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_and_params() {
+        let source = r#"
+            type Msg { Greet { name: String, times: Int }, Bye { } }
+            agent Starter {
+                state { }
+                on start -> {
+                    send Greeter Greet { times: 2, name: "ann" };
+                    send Greeter Bye { };
+                }
+            }
+            agent Greeter {
+                state { }
+                on Greet { name, times } -> { log("hello", name, times); }
+                on Bye { } -> { log("bye"); }
+            }
+        "#;
+        assert_eq!(run(source).await.unwrap(), ["hello ann 2", "bye"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_to_stopped_agent() {
+        let source = r#"
+            type Ping { Ping { } }
+            type Go { Go { } }
+            agent Sender : Go {
+                state { }
+                on start -> { schedule 10ms send self Go { }; }
+                on Go { } -> { send Dud Ping { }; }
+            }
+            agent Dud : Ping {
+                state { }
+                on start -> { log(9223372036854775807 + 1); }
+                on Ping { } -> { }
+            }
+            supervisor S { strategy: one_for_one, max_restarts: 0 within 1s, children { Sender } }
+        "#;
+        let error = run(source).await.unwrap_err().to_string();
+        assert!(error.ends_with("child Sender failed: Cannot message ref #1, whose agent has stopped"), "{}", error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_state_refers_to_later_agents() {
+        let source = r#"
+            type Msg { Ping { from: String } }
+            agent A : Msg {
+                state { peer: Ref[Msg] = B; supervised: Ref[Msg] = W; }
+                on start -> {
+                    send state.peer Ping { from: "a" };
+                    send state.supervised Ping { from: "a" };
+                }
+                on Ping { from } -> { }
+            }
+            agent B : Msg {
+                state { }
+                on Ping { from } -> { log("b got ping from", from); }
+            }
+            agent W : Msg {
+                state { }
+                on Ping { from } -> { log("w got ping from", from); }
+            }
+            supervisor S { strategy: one_for_one, children { W } }
+        "#;
+        let mut lines = run(source).await.unwrap();
+        lines.sort();
+        assert_eq!(lines, ["b got ping from a", "w got ping from a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_short_circuit() {
+        let source = r#"
+            fn noisy(result: Bool) -> Bool uses log { log("evaluated", result); return result; }
+            agent A {
+                state { }
+                on start -> {
+                    if false && noisy(true) { log("and"); }
+                    if true || noisy(false) { log("or"); }
+                    if noisy(true) && noisy(false) { log("both"); }
+                }
+            }
+        "#;
+        assert_eq!(run(source).await.unwrap(), ["or", "evaluated true", "evaluated false"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_runtime_errors() {
+        let budget = failure("while true { state.n = state.n + 1; }").await;
+        assert_eq!(budget, "Handler start exceeded its iteration budget of 1000");
+        let depth = failure("let n = deeper(0);").await;
+        assert_eq!(depth, "Handler start exceeded the maximum call depth of 256");
+        let overflow = failure("log(9223372036854775807 + 1);").await;
+        assert_eq!(overflow, "Integer overflow in 9223372036854775807 + 1");
+        let negation = failure("state.n = -9223372036854775807 - 1; log(-state.n);").await;
+        assert_eq!(negation, "Integer overflow negating -9223372036854775808");
+
+        let min = Value::Int(i64::MIN);
+        assert!(eval_binop(&BinOp::Div, &min, &Value::Int(-1)).is_err());
+        assert!(eval_binop(&BinOp::Mod, &min, &Value::Int(-1)).is_err());
+        assert!(eval_binop(&BinOp::Mul, &min, &Value::Int(2)).is_err());
+        assert!(eval_binop(&BinOp::Sub, &min, &Value::Int(1)).is_err());
+        assert_eq!(eval_binop(&BinOp::Div, &min, &Value::Int(1)).unwrap(), min);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_spawn_and_ask() {
+        let source = r#"
+            type Report { Done { worker: Int, total: Int } }
+            type Job { Work { amount: Int }, Total { replyTo: Ref[Count] } }
+            type Count { Is { n: Int } }
+            agent Boss : Report {
+                state { }
+                on start -> {
+                    let w = spawn Worker(7, self);
+                    send w Work { amount: 3 };
+                }
+                on Done { worker, total } -> {
+                    log("worker", worker, "reports", total);
+                }
+            }
+            agent Worker(id: Int, boss: Ref[Report]) : Job {
+                state { total: Int = id * 10; }
+                on Work { amount } -> {
+                    state.total = state.total + amount;
+                    send boss Done { worker: id, total: state.total };
+                }
+                on Total { replyTo } -> { send replyTo Is { n: state.total }; }
+            }
+            agent Client {
+                state { }
+                on start -> {
+                    let answer = ask Server Total { } within 2s;
+                    match answer {
+                        some(reply) => { match reply { Is { n } => { log("server total", n); } } }
+                        none => { log("no answer"); }
+                    }
+                }
+            }
+            agent Server : Job {
+                state { }
+                on Work { amount } -> { }
+                on Total { replyTo } -> { send replyTo Is { n: 42 }; }
+            }
+        "#;
+        let mut lines = run(source).await.unwrap();
+        lines.sort();
+        assert_eq!(lines, ["server total 42", "worker 7 reports 73"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_schedule() {
+        let source = r#"
+            type Msg { Tick { }, Stop { } }
+            agent Clock : Msg {
+                state { ticks: Int = 0; heartbeat: Option[Timer] = none; }
+                on start -> {
+                    state.heartbeat = some(every 100ms send self Tick { });
+                    let cancelled = schedule 50ms send self Stop { };
+                    log("cancelled", cancelled.cancel());
+                    schedule 450ms send self Stop { };
+                }
+                on Tick { } -> { state.ticks = state.ticks + 1; }
+                on Stop { } -> {
+                    match state.heartbeat {
+                        some(h) => { log("ticks", state.ticks, "stopped", h.cancel()); }
+                        none => { }
+                    }
+                }
+            }
+        "#;
+        assert_eq!(run(source).await.unwrap(), ["cancelled true", "ticks 4 stopped true"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervision_and_hooks() {
+        let source = r#"
+            type Msg { Job { id: Int }, Crash { } }
+            agent Worker : Msg {
+                state { done: Int = 0; }
+                on start -> { log("start with", state.done); }
+                on restart -> { log("restart"); }
+                on stop -> { log("stop after", state.done); }
+                on Job { id } -> {
+                    state.done = state.done + 1;
+                    if id == 1 { send self Crash { }; }
+                }
+                on Crash { } -> { let xs: List[Int] = []; let x = xs[0]; }
+            }
+            agent Client {
+                state { }
+                on start -> { send Worker Job { id: 1 }; }
+            }
+            supervisor Root { strategy: one_for_one, children { Worker } }
+        "#;
+        let lines = run(source).await.unwrap();
+        // The restarted worker starts from fresh state; the program ends with
+        // a final stop once idle
+        assert_eq!(lines, ["start with 0", "stop after 1", "restart", "start with 0", "stop after 0"]);
+
+        let giving_up = source.replace("children { Worker }", "max_restarts: 0 within 1s, children { Worker }");
+        let error = run(&giving_up).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Supervisor Root gave up after more than 0 restarts within 1s; child Worker failed: Index 0 out of bounds for list of length 0"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_imported_agents_start_when_supervised() {
+        let dir = crate::modules::tests::project(
+            "startup",
+            &[
+                (
+                    "workers.agent",
+                    "pub agent Idle { state { } on start -> { log(\"idle started\"); } } \
+                     pub agent Kept { state { } on start -> { log(\"kept started\"); } }",
+                ),
+                (
+                    "main.agent",
+                    "import \"workers.agent\"; agent Main { state { } on start -> { log(\"main started\"); } } \
+                     supervisor S { strategy: one_for_one, children { workers::Kept } }",
+                ),
+                ("named.agent", "import \"workers.agent\"; agent Main { state { } on start -> { let w = workers::Idle; } }"),
+            ],
+        );
+
+        let program = crate::modules::tests::load(&dir, "main.agent").expect("resolves");
+        let mut lines = run_program(program, Config::default()).await.unwrap();
+        lines.sort();
+        assert_eq!(lines, ["kept started", "main started"]);
+
+        // Nor can an agent that never starts be referred to by name
+        let path = dir.join("named.agent");
+        let mut sources = crate::diagnostics::SourceMap::default();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let program = crate::modules::load_project(path.to_str().unwrap(), source, &mut sources).expect("resolves");
+        let error = &crate::typechecker::typecheck(&program).unwrap_err()[0];
+        let (span, file) = (error.primary.span, sources.file_of(error.primary.span.start));
+        let text = &file.source[span.start - file.base..span.end - file.base];
+        assert_eq!(
+            (error.message.as_str(), text),
+            ("agent `workers::Idle` has no instance to refer to by name", "workers::Idle")
+        );
     }
 }
@@ -3,7 +3,10 @@ use anyhow::Result;
 
 mod ast;
 mod bytecode;
+mod diagnostics;
 mod interpreter;
+mod modules;
+mod strings;
 mod typechecker;
 
 // LALRPOP-generated parser module wrapper in src/grammar.rs
@@ -11,25 +14,42 @@ mod grammar;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args = std::env::args().skip(1);
+    let mut path = None;
+    let mut config = interpreter::Config::default();
 
-    if args.len() < 2 {
-        eprintln!("Usage: agentc <source.agent>");
-        std::process::exit(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iteration-budget" => {
+                config.iteration_budget = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| anyhow::anyhow!("--iteration-budget expects a number"))?;
+            }
+            _ => path = Some(arg),
+        }
     }
 
-    let source = std::fs::read_to_string(&args[1])?;
+    let Some(path) = path else {
+        eprintln!("Usage: agentc [--iteration-budget <n>] <source.agent>");
+        std::process::exit(1);
+    };
+    let path = &path;
+    let source = std::fs::read_to_string(path)?;
 
-    // Parse
-    let parser = grammar::ProgramParser::new();
-    let program = parser
-        .parse(&source)
-        .map_err(|e| anyhow::anyhow!("Parse error: {:?}", e))?;
+    // Parse the file and everything it imports
+    let mut sources = diagnostics::SourceMap::default();
+    let program = match modules::load_project(path, source, &mut sources) {
+        Ok(program) => program,
+        Err(diagnostics) => report(path, &sources, diagnostics),
+    };
 
     println!("✓ Parsed successfully");
 
     // Type check
-    typechecker::typecheck(&program)?;
+    if let Err(diagnostics) = typechecker::typecheck(&program) {
+        report(path, &sources, diagnostics);
+    }
     println!("✓ Type checked successfully");
 
     // Compile to bytecode
@@ -38,7 +58,19 @@ async fn main() -> Result<()> {
 
     // Execute
     println!("\nExecuting...\n");
-    interpreter::execute(bytecode_program).await?;
+    interpreter::execute(bytecode_program, config).await?;
 
     Ok(())
 }
+
+/// Print every diagnostic in source order and exit
+fn report(path: &str, sources: &diagnostics::SourceMap, mut diagnostics: Vec<diagnostics::Diagnostic>) -> ! {
+    diagnostics.sort_by_key(|d| d.primary.span.start);
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.render(sources));
+    }
+    let count = diagnostics.len();
+    let plural = if count == 1 { "" } else { "s" };
+    eprintln!("error: could not compile `{}` due to {} previous error{}", path, count, plural);
+    std::process::exit(1);
+}
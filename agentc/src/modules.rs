@@ -0,0 +1,693 @@
+// Module loading and name resolution across `import`ed files
+use crate::ast::*;
+use crate::diagnostics::{Diagnostic, SourceMap};
+use crate::grammar::ProgramParser;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Load the root file and everything it imports, merging them into one
+/// program. Items of imported modules are renamed to `module::Item` and
+/// every reference is rewritten to the name it resolves to.
+pub fn load_project(path: &str, source: String, sources: &mut SourceMap) -> Result<Program, Vec<Diagnostic>> {
+    let mut loader = Loader {
+        sources,
+        modules: Vec::new(),
+        by_path: HashMap::new(),
+        loading: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    loader.load(Path::new(path), Some(source), None);
+    if !loader.diagnostics.is_empty() {
+        return Err(loader.diagnostics);
+    }
+
+    let mut diagnostics = Vec::new();
+    let mut merged = Program::default();
+    let root = loader.modules.len() - 1;
+    for (index, module) in loader.modules.iter().enumerate() {
+        let prefix = (index != root).then_some(module.name.as_str());
+        let scope = Scope::new(module, prefix, &loader.modules, &mut diagnostics);
+        let mut resolver = Resolver {
+            scope: &scope,
+            locals: Vec::new(),
+            type_params: Vec::new(),
+            diagnostics: &mut diagnostics,
+        };
+        let program = resolver.program(module.program.clone(), prefix);
+        merged.types.extend(program.types);
+        merged.functions.extend(program.functions);
+        merged.agents.extend(program.agents);
+        merged.supervisors.extend(program.supervisors);
+    }
+
+    if diagnostics.is_empty() {
+        Ok(merged)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// One parsed file
+struct Module {
+    /// The file stem, used to qualify its items elsewhere
+    name: String,
+    path: PathBuf,
+    program: Program,
+    /// Indices into `Loader::modules` of the modules this one imports
+    imports: Vec<usize>,
+}
+
+struct Loader<'a> {
+    sources: &'a mut SourceMap,
+    /// Dependencies always come before their importers; the root is last
+    modules: Vec<Module>,
+    by_path: HashMap<PathBuf, usize>,
+    /// Files whose imports are being loaded, outermost first
+    loading: Vec<PathBuf>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Loader<'_> {
+    /// Load a file (reading it unless its source is given), returning its index
+    fn load(&mut self, path: &Path, source: Option<String>, import: Option<&Import>) -> Option<usize> {
+        let error = |message: String| match import {
+            Some(import) => Diagnostic::error(message, import.span),
+            None => Diagnostic::error(message, Span::default()),
+        };
+
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(start) = self.loading.iter().position(|p| *p == key) {
+            let cycle: Vec<String> = self.loading[start..]
+                .iter()
+                .chain([&key])
+                .map(|p| file_name(p))
+                .collect();
+            self.diagnostics.push(
+                error(format!("import cycle: {}", cycle.join(" -> ")))
+                    .with_primary("imported here")
+                    .with_note("modules may not import each other, directly or indirectly"),
+            );
+            return None;
+        }
+        if let Some(&index) = self.by_path.get(&key) {
+            return Some(index);
+        }
+
+        // The root's items keep plain names, so only imported files need a usable module name
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+        if import.is_some() && !is_identifier(&name) {
+            self.diagnostics.push(
+                error(format!("module name `{}` is not a valid identifier", name))
+                    .with_note("module names come from file names, e.g. `tickets.agent` is `tickets`"),
+            );
+            return None;
+        }
+        if let Some(other) = self.modules.iter().find(|m| m.name == name && import.is_some()) {
+            self.diagnostics.push(error(format!(
+                "two modules are named `{}`: {} and {}",
+                name,
+                other.path.display(),
+                path.display()
+            )));
+            return None;
+        }
+
+        let source = match source.map(Ok).unwrap_or_else(|| std::fs::read_to_string(path)) {
+            Ok(source) => source,
+            Err(e) => {
+                self.diagnostics
+                    .push(error(format!("cannot read `{}`: {}", path.display(), e)).with_primary("imported here"));
+                return None;
+            }
+        };
+        let base = self.sources.add(path.display().to_string(), source.as_str());
+        let program = match ProgramParser::new().parse(base, &source) {
+            Ok(program) => program,
+            Err(e) => {
+                self.diagnostics.push(Diagnostic::from_parse_error(base, e));
+                return None;
+            }
+        };
+
+        // Imports are relative to the importing file
+        self.loading.push(key.clone());
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut imports = Vec::new();
+        for import in &program.imports {
+            if let Some(index) = self.load(&dir.join(&import.path), None, Some(import)) {
+                imports.push(index);
+            }
+        }
+        self.loading.pop();
+
+        self.modules.push(Module {
+            name,
+            path: path.to_path_buf(),
+            program,
+            imports,
+        });
+        let index = self.modules.len() - 1;
+        self.by_path.insert(key, index);
+        Some(index)
+    }
+}
+
+/// The separate namespaces items live in
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Type,
+    Function,
+    Agent,
+    Supervisor,
+}
+
+/// Names visible in one module, mapped to the names they resolve to
+struct Scope {
+    names: HashMap<(Kind, String), String>,
+    /// `module::Item` paths that exist but are not `pub`
+    private: HashSet<(Kind, String)>,
+    /// Names of the modules this one imports
+    imported: HashSet<String>,
+}
+
+impl Scope {
+    fn new(module: &Module, prefix: Option<&str>, modules: &[Module], diagnostics: &mut Vec<Diagnostic>) -> Self {
+        let mut scope = Scope {
+            names: HashMap::new(),
+            private: HashSet::new(),
+            imported: HashSet::new(),
+        };
+        for (kind, name, _) in items(&module.program) {
+            scope.names.insert((kind, name.to_string()), qualify(prefix, name));
+        }
+
+        for &index in &module.imports {
+            let imported = &modules[index];
+            scope.imported.insert(imported.name.clone());
+            for (kind, name, public) in items(&imported.program) {
+                let path = format!("{}::{}", imported.name, name);
+                if public {
+                    scope.names.insert((kind, path.clone()), path);
+                } else {
+                    scope.private.insert((kind, path));
+                }
+            }
+        }
+
+        for use_decl in &module.program.uses {
+            scope.add_use(use_decl, module, modules, diagnostics);
+        }
+        scope
+    }
+
+    fn add_use(&mut self, use_decl: &Use, module: &Module, modules: &[Module], diagnostics: &mut Vec<Diagnostic>) {
+        let Some(&index) = module.imports.iter().find(|&&i| modules[i].name == use_decl.module) else {
+            diagnostics.push(
+                Diagnostic::error(format!("unresolved module `{}`", use_decl.module), use_decl.span)
+                    .with_note(format!("add `import \"{}.agent\";` first", use_decl.module)),
+            );
+            return;
+        };
+
+        let found: Vec<(Kind, bool)> = items(&modules[index].program)
+            .filter(|(_, name, _)| *name == use_decl.name)
+            .map(|(kind, _, public)| (kind, public))
+            .collect();
+        if found.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                format!("module `{}` has no item `{}`", use_decl.module, use_decl.name),
+                use_decl.span,
+            ));
+        } else if found.iter().all(|(_, public)| !public) {
+            diagnostics.push(
+                Diagnostic::error(
+                    format!("`{}` is private to module `{}`", use_decl.name, use_decl.module),
+                    use_decl.span,
+                )
+                .with_note(format!("declare it with `pub` in `{}` to use it here", use_decl.module)),
+            );
+        }
+
+        let path = format!("{}::{}", use_decl.module, use_decl.name);
+        for (kind, _) in found.into_iter().filter(|(_, public)| *public) {
+            let key = (kind, use_decl.name.clone());
+            if self.names.contains_key(&key) {
+                diagnostics.push(Diagnostic::error(
+                    format!("`{}` is already defined in this module", use_decl.name),
+                    use_decl.span,
+                ));
+                continue;
+            }
+            self.names.insert(key, path.clone());
+        }
+    }
+}
+
+/// Every item a module declares, with whether it is `pub`
+fn items(program: &Program) -> impl Iterator<Item = (Kind, &str, bool)> {
+    let types = program.types.iter().map(|t| (Kind::Type, t.name.as_str(), t.public));
+    let functions = program.functions.iter().map(|f| (Kind::Function, f.name.as_str(), f.public));
+    let agents = program.agents.iter().map(|a| (Kind::Agent, a.name.as_str(), a.public));
+    let supervisors = program.supervisors.iter().map(|s| (Kind::Supervisor, s.name.as_str(), s.public));
+    types.chain(functions).chain(agents).chain(supervisors)
+}
+
+/// Whether an item of a merged program comes from an imported module
+pub fn is_imported(name: &str) -> bool {
+    name.contains("::")
+}
+
+fn qualify(prefix: Option<&str>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}::{}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned())
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Rewrites one module's names to the ones they resolve to
+struct Resolver<'a> {
+    scope: &'a Scope,
+    /// Locals in scope, which shadow agent names
+    locals: Vec<String>,
+    /// Type parameters of the enclosing generic type or agent, which shadow type names
+    type_params: Vec<String>,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl Resolver<'_> {
+    fn program(&mut self, mut program: Program, prefix: Option<&str>) -> Program {
+        for type_def in &mut program.types {
+            type_def.name = qualify(prefix, &type_def.name);
+            self.type_params = type_def.params.clone();
+            for field in type_def.variants.iter_mut().flat_map(|v| &mut v.fields) {
+                self.ty(&mut field.ty, field.span);
+            }
+        }
+        self.type_params.clear();
+
+        for function in &mut program.functions {
+            function.name = qualify(prefix, &function.name);
+            for param in &mut function.params {
+                self.ty(&mut param.ty, param.span);
+                self.locals.push(param.name.clone());
+            }
+            if let Some(ret) = &mut function.ret {
+                self.ty(ret, function.span);
+            }
+            self.block(&mut function.body);
+            self.locals.clear();
+        }
+
+        for agent in &mut program.agents {
+            agent.name = qualify(prefix, &agent.name);
+            self.type_params = agent.type_params.clone();
+            if let Some(protocol) = &mut agent.protocol {
+                let mut ty = Type::Named(protocol.clone(), std::mem::take(&mut agent.protocol_args));
+                self.ty(&mut ty, agent.span);
+                if let Type::Named(resolved, args) = ty {
+                    *protocol = resolved;
+                    agent.protocol_args = args;
+                }
+            }
+            // Constructor parameters are in scope throughout the agent
+            let params: Vec<String> = agent.params.iter().map(|p| p.name.clone()).collect();
+            for param in &mut agent.params {
+                self.ty(&mut param.ty, param.span);
+            }
+            self.locals = params.clone();
+            for state_var in &mut agent.state {
+                self.ty(&mut state_var.ty, state_var.span);
+                self.expr(&mut state_var.init);
+            }
+            for handler in &mut agent.handlers {
+                self.locals = params.iter().chain(&handler.params).cloned().collect();
+                self.block(&mut handler.body);
+            }
+            for hook in &mut agent.hooks {
+                self.locals = params.clone();
+                self.block(&mut hook.body);
+            }
+            self.locals.clear();
+            self.type_params.clear();
+        }
+
+        // Children are agents or nested supervisors
+        for supervisor in &mut program.supervisors {
+            supervisor.name = qualify(prefix, &supervisor.name);
+            for child in &mut supervisor.children {
+                let resolved = self
+                    .lookup(Kind::Agent, &child.name)
+                    .or_else(|| self.lookup(Kind::Supervisor, &child.name));
+                match resolved {
+                    Some(resolved) => child.name = resolved,
+                    None if self.scope.private.contains(&(Kind::Supervisor, child.name.clone())) => {
+                        self.check_path(Kind::Supervisor, &child.name, child.span)
+                    }
+                    None => self.check_path(Kind::Agent, &child.name, child.span),
+                }
+            }
+        }
+        program
+    }
+
+    fn lookup(&self, kind: Kind, name: &str) -> Option<String> {
+        self.scope.names.get(&(kind, name.to_string())).cloned()
+    }
+
+    fn ty(&mut self, ty: &mut Type, span: Span) {
+        match ty {
+            Type::Named(name, args) if args.is_empty() && self.type_params.contains(name) => {}
+            Type::Named(name, args) | Type::Ref(name, args) => {
+                match self.lookup(Kind::Type, name) {
+                    Some(resolved) => *name = resolved,
+                    None => self.check_path(Kind::Type, name, span),
+                }
+                for arg in args {
+                    self.ty(arg, span);
+                }
+            }
+            Type::List(elem) | Type::Option(elem) => self.ty(elem, span),
+            Type::Map(key, value) => {
+                self.ty(key, span);
+                self.ty(value, span);
+            }
+            _ => {}
+        }
+    }
+
+    /// Explain an unresolved `module::Item`; plain unknown names are left
+    /// for the typechecker to report
+    fn check_path(&mut self, kind: Kind, name: &str, span: Span) {
+        let Some((module, item)) = name.split_once("::") else {
+            return;
+        };
+        if self.scope.private.contains(&(kind, name.to_string())) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("`{}` is private to module `{}`", item, module), span)
+                    .with_note(format!("declare it with `pub` in `{}` to use it here", module)),
+            );
+        } else if !self.scope.imported.contains(module) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("unresolved module `{}`", module), span)
+                    .with_note(format!("add `import \"{}.agent\";` first", module)),
+            );
+        }
+    }
+
+    fn block(&mut self, body: &mut [Stmt]) {
+        let mark = self.locals.len();
+        for stmt in body {
+            self.stmt(stmt);
+        }
+        self.locals.truncate(mark);
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::Let { name, ty, value } => {
+                self.expr(value);
+                if let Some(ty) = ty {
+                    self.ty(ty, stmt.span);
+                }
+                self.locals.push(name.clone());
+            }
+            StmtKind::Assign { target: _, value } => self.expr(value),
+            StmtKind::Send { target, args, .. } => {
+                self.expr(target);
+                for (_, arg) in args {
+                    self.expr(arg);
+                }
+            }
+            StmtKind::Effect { name, args } => {
+                match self.lookup(Kind::Function, name) {
+                    Some(resolved) => *name = resolved,
+                    None => self.check_path(Kind::Function, name, stmt.span),
+                }
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                self.expr(cond);
+                self.block(then_body);
+                self.block(else_body);
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                self.expr(scrutinee);
+                for arm in arms {
+                    let mark = self.locals.len();
+                    match &arm.pattern {
+                        Pattern::Variant { bindings, .. } => self.locals.extend(bindings.iter().cloned()),
+                        Pattern::Some(binding) => self.locals.push(binding.clone()),
+                        Pattern::None | Pattern::Wildcard => {}
+                    }
+                    self.block(&mut arm.body);
+                    self.locals.truncate(mark);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.expr(cond);
+                self.block(body);
+            }
+            StmtKind::For { var, iter, body } => {
+                match iter {
+                    ForIter::Range { start, end } => {
+                        self.expr(start);
+                        self.expr(end);
+                    }
+                    ForIter::List(list) => self.expr(list),
+                }
+                let mark = self.locals.len();
+                self.locals.push(var.clone());
+                self.block(body);
+                self.locals.truncate(mark);
+            }
+            StmtKind::Break | StmtKind::Continue | StmtKind::Return(None) => {}
+            StmtKind::Return(Some(expr)) | StmtKind::Expr(expr) => self.expr(expr),
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        match &mut expr.kind {
+            ExprKind::Var(name) => {
+                if !self.locals.contains(name) {
+                    match self.lookup(Kind::Agent, name) {
+                        Some(resolved) => *name = resolved,
+                        None => self.check_path(Kind::Agent, name, expr.span),
+                    }
+                }
+            }
+            ExprKind::Construct { type_name, fields, .. } => {
+                match self.lookup(Kind::Type, type_name) {
+                    Some(resolved) => *type_name = resolved,
+                    None => self.check_path(Kind::Type, type_name, expr.span),
+                }
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Spawn { agent, type_args, args } => {
+                match self.lookup(Kind::Agent, agent) {
+                    Some(resolved) => *agent = resolved,
+                    None => self.check_path(Kind::Agent, agent, expr.span),
+                }
+                for ty in type_args {
+                    self.ty(ty, expr.span);
+                }
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Ask { target, args, timeout, .. } => {
+                self.expr(target);
+                for (_, arg) in args {
+                    self.expr(arg);
+                }
+                self.expr(timeout);
+            }
+            ExprKind::Schedule { delay, target, args, .. } => {
+                self.expr(delay);
+                self.expr(target);
+                for (_, arg) in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Call { name, args } => {
+                match self.lookup(Kind::Function, name) {
+                    Some(resolved) => *name = resolved,
+                    None => self.check_path(Kind::Function, name, expr.span),
+                }
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Interpolate(parts) => {
+                for part in parts {
+                    if let StrPart::Expr(expr) = part {
+                        self.expr(expr);
+                    }
+                }
+            }
+            ExprKind::BinOp { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::Unary { operand, .. } => self.expr(operand),
+            ExprKind::FieldAccess { obj, .. } => self.expr(obj),
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExprKind::Some(value) => self.expr(value),
+            ExprKind::Index { obj, index } => {
+                self.expr(obj);
+                self.expr(index);
+            }
+            ExprKind::MethodCall { receiver, args, .. } => {
+                self.expr(receiver);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::StateField(_)
+            | ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Duration(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_)
+            | ExprKind::SelfRef
+            | ExprKind::None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A temporary directory of source files, removed when dropped so a
+    /// failing test cleans up too
+    pub(crate) struct Project(PathBuf);
+
+    impl std::ops::Deref for Project {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for Project {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    pub(crate) fn project(name: &str, files: &[(&str, &str)]) -> Project {
+        let dir = Project(std::env::temp_dir().join(format!("agentc-modules-{}-{}", name, std::process::id())));
+        fs::create_dir_all(&dir.0).unwrap();
+        for (file, source) in files {
+            fs::write(dir.join(file), source).unwrap();
+        }
+        dir
+    }
+
+    pub(crate) fn load(dir: &Path, root: &str) -> Result<Program, Vec<Diagnostic>> {
+        let path = dir.join(root);
+        let source = fs::read_to_string(&path).unwrap();
+        load_project(path.to_str().unwrap(), source, &mut SourceMap::default())
+    }
+
+    /// Each error loading `root`, with the source text its primary span covers
+    fn errors(dir: &Path, root: &str) -> Vec<(String, String)> {
+        let path = dir.join(root);
+        let source = fs::read_to_string(&path).unwrap();
+        let mut sources = SourceMap::default();
+        let errors = load_project(path.to_str().unwrap(), source, &mut sources).unwrap_err();
+        errors
+            .into_iter()
+            .map(|error| {
+                let span = error.primary.span;
+                let file = sources.file_of(span.start);
+                (error.message, file.source[span.start - file.base..span.end - file.base].to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_imports_and_visibility() {
+        let dir = project(
+            "visibility",
+            &[
+                (
+                    "shared.agent",
+                    "pub type Msg { Ping { } } type Hidden { H { } } pub fn twice(x: Int) -> Int { return x * 2; } \
+                     fn thrice(x: Int) -> Int { return x * 3; } pub agent Echo : Msg { state { } on Ping { } -> { } } \
+                     agent Quiet : Msg { state { } on Ping { } -> { } }",
+                ),
+                (
+                    "main.agent",
+                    "import \"shared.agent\"; use shared::twice; agent A : shared::Msg { state { } on Ping { } -> { \
+                     let x = twice(1) + shared::twice(2); send shared::Echo Ping { }; if (shared::Echo) == self { } } }",
+                ),
+                ("private.agent", "import \"shared.agent\"; use shared::Hidden;"),
+                (
+                    "calls.agent",
+                    "import \"shared.agent\"; agent A { state { } on start -> { let x = shared::thrice(1); send shared::Quiet Ping { }; } }",
+                ),
+                ("syntax.agent", "import \"broken.agent\";"),
+                ("broken.agent", "fn f() -> Int { return 1 + ; }"),
+                ("a.agent", "import \"b.agent\";"),
+                ("b.agent", "import \"a.agent\";"),
+            ],
+        );
+
+        let program = load(&dir, "main.agent").unwrap();
+        assert_eq!(program.types[0].name, "shared::Msg");
+        assert_eq!(program.agents[0].protocol.as_deref(), Some("shared::Msg"));
+        assert_eq!(program.functions[0].name, "shared::twice");
+
+        let private = [("`Hidden` is private to module `shared`".to_string(), "use shared::Hidden".to_string())];
+        assert_eq!(errors(&dir, "private.agent"), private);
+
+        // Qualified calls and agent names resolve and are checked like types
+        let agent = &program.agents.iter().find(|a| a.name == "A").unwrap();
+        let StmtKind::Let { value, .. } = &agent.handlers[0].body[0].kind else { panic!() };
+        let ExprKind::BinOp { right, .. } = &value.kind else { panic!() };
+        assert!(matches!(&right.kind, ExprKind::Call { name, .. } if name == "shared::twice"));
+        let calls = [
+            ("`thrice` is private to module `shared`".to_string(), "shared::thrice(1)".to_string()),
+            ("`Quiet` is private to module `shared`".to_string(), "shared::Quiet".to_string()),
+        ];
+        assert_eq!(errors(&dir, "calls.agent"), calls);
+
+        // Syntax errors point into the file they are in
+        let syntax = [("unexpected token `;`".to_string(), ";".to_string())];
+        assert_eq!(errors(&dir, "syntax.agent"), syntax);
+
+        let cycle = [("import cycle: a.agent -> b.agent -> a.agent".to_string(), "import \"a.agent\"".to_string())];
+        assert_eq!(errors(&dir, "a.agent"), cycle);
+    }
+}
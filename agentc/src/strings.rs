@@ -0,0 +1,144 @@
+// String literal decoding: escapes and `${expr}` interpolation
+use crate::ast::{Expr, ExprKind, Span, StrPart};
+use crate::diagnostics::Diagnostic;
+use crate::grammar::ExprParser;
+
+/// Decode a quoted string token found at offset `start` of a file whose
+/// spans are offset by `file_base`
+pub fn parse_literal(file_base: usize, start: usize, token: &str) -> Result<ExprKind, Diagnostic> {
+    let body = &token[1..token.len() - 1];
+    let local = start + 1;
+    let base = file_base + local;
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let (_, escape) = chars.next().expect("the lexer never ends a string on a backslash");
+                let decoded = match escape {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    '"' | '\\' | '$' => escape,
+                    'u' => unicode_escape(body, base, i, &mut chars)?,
+                    other => {
+                        let span = Span::new(base + i, base + i + 1 + other.len_utf8());
+                        return Err(Diagnostic::error(format!("unknown escape `\\{}`", other), span)
+                            .with_note("supported escapes are \\n \\t \\r \\0 \\\" \\\\ \\$ and \\u{..}"));
+                    }
+                };
+                text.push(decoded);
+            }
+            '$' if chars.peek().map(|&(_, c)| c) == Some('{') => {
+                chars.next();
+                let open = i + 2;
+                let close = find_closing_brace(body, open).ok_or_else(|| {
+                    Diagnostic::error("unterminated interpolation", Span::new(base + i, base + body.len()))
+                        .with_primary("missing `}`")
+                })?;
+                if body[open..close].trim().is_empty() {
+                    return Err(Diagnostic::error(
+                        "empty interpolation",
+                        Span::new(base + i, base + close + 1),
+                    ));
+                }
+                if !text.is_empty() {
+                    parts.push(StrPart::Lit(std::mem::take(&mut text)));
+                }
+                parts.push(StrPart::Expr(parse_embedded(&body[open..close], file_base, local + open)?));
+                while chars.peek().is_some_and(|&(j, _)| j <= close) {
+                    chars.next();
+                }
+            }
+            c => text.push(c),
+        }
+    }
+
+    if parts.is_empty() {
+        return Ok(ExprKind::Str(text));
+    }
+    if !text.is_empty() {
+        parts.push(StrPart::Lit(text));
+    }
+    Ok(ExprKind::Interpolate(parts))
+}
+
+/// Decode the `{XXXX}` after `\u`, where `at` is the offset of the backslash
+fn unicode_escape(
+    body: &str,
+    base: usize,
+    at: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<char, Diagnostic> {
+    let rest = &body[at + 2..];
+    let invalid = |len: usize| {
+        Diagnostic::error("invalid unicode escape", Span::new(base + at, base + at + 2 + len))
+            .with_note("write it as \\u{1F990}, with 1 to 6 hex digits")
+    };
+    let end = rest.find('}').filter(|_| rest.starts_with('{')).ok_or_else(|| invalid(0))?;
+    let hex = &rest[1..end];
+    let decoded = (1..=6)
+        .contains(&hex.len())
+        .then(|| u32::from_str_radix(hex, 16).ok())
+        .flatten()
+        .and_then(char::from_u32)
+        .ok_or_else(|| invalid(end + 1))?;
+    while chars.peek().is_some_and(|&(j, _)| j <= at + 2 + end) {
+        chars.next();
+    }
+    Ok(decoded)
+}
+
+/// Offset of the `}` closing a `${` whose contents start at `open`
+fn find_closing_brace(body: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in body[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(open + i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse the expression inside `${..}`, found at `offset` in its file
+fn parse_embedded(source: &str, file_base: usize, offset: usize) -> Result<Expr, Diagnostic> {
+    let base = file_base + offset;
+    ExprParser::new()
+        .parse(base, source)
+        .map_err(|e| Diagnostic::from_parse_error(base, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escapes_and_interpolation() {
+        match parse_literal(0, 0, r#""a\n\"b\" \\ \u{41} \${x}""#).unwrap() {
+            ExprKind::Str(s) => assert_eq!(s, "a\n\"b\" \\ A ${x}"),
+            other => panic!("expected a plain string, got {:?}", other),
+        }
+
+        let source = r#""id ${id + 1}!""#;
+        let ExprKind::Interpolate(parts) = parse_literal(0, 10, source).unwrap() else {
+            panic!("expected an interpolation");
+        };
+        assert!(matches!(&parts[0], StrPart::Lit(s) if s == "id "));
+        let StrPart::Expr(expr) = &parts[1] else { panic!("expected an expression") };
+        assert_eq!(expr.span, Span::new(16, 22));
+        assert!(matches!(&parts[2], StrPart::Lit(s) if s == "!"));
+
+        assert_eq!(parse_literal(0, 0, r#""\q""#).unwrap_err().message, "unknown escape `\\q`");
+        assert_eq!(parse_literal(0, 0, r#""${id""#).unwrap_err().message, "unterminated interpolation");
+
+        // Errors inside an interpolation point into the file
+        let error = parse_literal(100, 10, r#""x ${1 +}""#).unwrap_err();
+        assert_eq!((error.message.as_str(), error.primary.span), ("unexpected end of file", Span::new(118, 118)));
+    }
+}
@@ -1,40 +1,146 @@
 // Type checking pass
 use crate::ast::*;
-use anyhow::{bail, Result};
-use std::collections::HashMap;
+use crate::diagnostics::Diagnostic;
+use crate::modules::is_imported;
+use std::collections::{HashMap, HashSet};
 
-pub fn typecheck(program: &Program) -> Result<()> {
-    let mut ctx = TypeContext::new();
+type Result<T> = std::result::Result<T, Diagnostic>;
+
+/// Check the whole program, reporting every error found
+pub fn typecheck(program: &Program) -> std::result::Result<(), Vec<Diagnostic>> {
+    let mut checker = Checker {
+        ctx: TypeContext::new(),
+        globals: HashMap::new(),
+        functions: HashMap::new(),
+        agents: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
 
     // Register all type definitions
     for type_def in &program.types {
-        ctx.register_type(type_def)?;
+        let result = checker.ctx.register_type(type_def);
+        checker.report(result);
+    }
+
+    // Field types may mention any type, so are resolved once all are registered
+    for type_def in &program.types {
+        let mut resolved = type_def.clone();
+        for field in resolved.variants.iter_mut().flat_map(|v| &mut v.fields) {
+            field.ty = checker.resolve(&field.ty, &type_def.params, field.span);
+        }
+        // A duplicate definition keeps the first one registered
+        if checker.ctx.get_type(&type_def.name).is_some_and(|t| t.span == type_def.span) {
+            checker.ctx.types.insert(type_def.name.clone(), resolved);
+        }
+    }
+
+    // Functions may call each other regardless of declaration order
+    for function in &program.functions {
+        checker.declare_function(function);
+    }
+
+    // Agents started with the program are addressable by name from every
+    // handler, and any agent can be spawned
+    let started = started(program);
+    for agent in &program.agents {
+        let ref_ty = match checker.ctx.protocol_of(agent).cloned() {
+            Some(protocol) => {
+                let args = match checker.ctx.protocol_args(agent, &protocol) {
+                    Ok(args) => args,
+                    Err(diagnostic) => {
+                        checker.diagnostics.push(diagnostic);
+                        vec![Type::Error; protocol.params.len()]
+                    }
+                };
+                Type::Ref(protocol.name, args)
+            }
+            // Reported when the agent is checked
+            None => Type::Error,
+        };
+        // Each mention of a generic agent infers its own type arguments
+        let holes = agent.type_params.iter().map(|p| (p.clone(), Type::Infer)).collect();
+        if ref_ty != Type::Error && agent.params.is_empty() && started.contains(agent.name.as_str()) {
+            checker.globals.insert(agent.name.clone(), substitute(&ref_ty, &holes));
+        }
+        let params = agent
+            .params
+            .iter()
+            .map(|param| Param {
+                ty: checker.resolve(&param.ty, &agent.type_params, param.span),
+                ..param.clone()
+            })
+            .collect();
+        let sig = AgentSig {
+            type_params: agent.type_params.clone(),
+            params,
+            ref_ty,
+            span: agent.span,
+        };
+        checker.agents.insert(agent.name.clone(), sig);
+    }
+
+    for function in &program.functions {
+        checker.check_function(function);
     }
 
     // Check each agent
     for agent in &program.agents {
-        check_agent(&ctx, agent)?;
+        checker.check_agent(agent);
     }
 
-    Ok(())
+    checker.check_supervisors(&program.supervisors);
+
+    if checker.diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.diagnostics)
+    }
 }
 
 struct TypeContext {
     types: HashMap<String, TypeDef>,
+    /// Type names in declaration order, so lookups by variant are deterministic
+    order: Vec<String>,
 }
 
 impl TypeContext {
     fn new() -> Self {
         Self {
             types: HashMap::new(),
+            order: Vec::new(),
         }
     }
 
     fn register_type(&mut self, type_def: &TypeDef) -> Result<()> {
-        if self.types.contains_key(&type_def.name) {
-            bail!("Duplicate type definition: {}", type_def.name);
+        if let Some(previous) = self.types.get(&type_def.name) {
+            return Err(Diagnostic::error(
+                format!("duplicate type definition `{}`", type_def.name),
+                type_def.span,
+            )
+            .with_primary("redefined here")
+            .with_label(previous.span, "first defined here"));
         }
         self.types.insert(type_def.name.clone(), type_def.clone());
+        self.order.push(type_def.name.clone());
+
+        for (i, param) in type_def.params.iter().enumerate() {
+            if type_def.params[..i].contains(param) {
+                return Err(Diagnostic::error(
+                    format!("duplicate type parameter `{}` in `{}`", param, type_def.name),
+                    type_def.span,
+                ));
+            }
+        }
+
+        for (i, variant) in type_def.variants.iter().enumerate() {
+            if let Some(previous) = type_def.variants[..i].iter().find(|v| v.name == variant.name) {
+                return Err(Diagnostic::error(
+                    format!("duplicate variant `{}` in `{}`", variant.name, type_def.name),
+                    variant.span,
+                )
+                .with_label(previous.span, "first defined here"));
+            }
+        }
         Ok(())
     }
 
@@ -49,84 +155,2052 @@ impl TypeContext {
             false
         }
     }
-}
 
-fn check_agent(ctx: &TypeContext, agent: &AgentDef) -> Result<()> {
-    let mut env = HashMap::new();
+    /// Every type declaring a variant, in declaration order
+    fn types_of_variant(&self, variant_name: &str) -> Vec<&TypeDef> {
+        self.order
+            .iter()
+            .filter_map(|name| self.types.get(name))
+            .filter(|t| t.variants.iter().any(|v| v.name == variant_name))
+            .collect()
+    }
+
+    fn get_variant(&self, type_name: &str, variant_name: &str) -> Option<&Variant> {
+        self.get_type(type_name)?
+            .variants
+            .iter()
+            .find(|v| v.name == variant_name)
+    }
+
+    /// The message type an agent accepts: declared, or inferred from its first
+    /// handler. Of several types declaring that variant the first declared is
+    /// used, and the ambiguity is reported when the agent is checked.
+    fn protocol_of(&self, agent: &AgentDef) -> Option<&TypeDef> {
+        match &agent.protocol {
+            Some(name) => self.get_type(name),
+            None => {
+                let handler = agent.handlers.first()?;
+                self.types_of_variant(&handler.variant).first().copied()
+            }
+        }
+    }
 
-    // Add state variables to environment
-    for state_var in &agent.state {
-        env.insert(state_var.name.clone(), state_var.ty.clone());
+    /// Type arguments of an agent's protocol, in terms of the agent's own type parameters
+    fn protocol_args(&self, agent: &AgentDef, protocol: &TypeDef) -> Result<Vec<Type>> {
+        if agent.protocol.is_none() && !protocol.params.is_empty() {
+            return Err(Diagnostic::error(
+                format!("cannot infer the type arguments of the protocol of `{}`", agent.name),
+                agent.span,
+            )
+            .with_label(protocol.span, format!("`{}` is generic", protocol.name))
+            .with_note(format!("declare it with `agent {} : {}[...]`", agent.name, protocol.name)));
+        }
+        self.resolve_args(&protocol.name, &agent.protocol_args, &agent.type_params, agent.span)
     }
 
-    // Check each handler
-    for handler in &agent.handlers {
-        check_handler(ctx, &env, handler)?;
+    /// Resolve an annotation, ensuring every user-defined type it mentions is
+    /// declared and given its type arguments; names of the type parameters in
+    /// scope become `Type::Param`
+    fn resolve_type(&self, ty: &Type, params: &[String], span: Span) -> Result<Type> {
+        match ty {
+            Type::Named(name, args) if args.is_empty() && params.contains(name) => Ok(Type::Param(name.clone())),
+            Type::Named(name, args) => Ok(Type::Named(name.clone(), self.resolve_args(name, args, params, span)?)),
+            Type::Ref(name, args) => Ok(Type::Ref(name.clone(), self.resolve_args(name, args, params, span)?)),
+            Type::List(elem) => Ok(Type::List(Box::new(self.resolve_type(elem, params, span)?))),
+            Type::Option(elem) => Ok(Type::Option(Box::new(self.resolve_type(elem, params, span)?))),
+            Type::Map(key, value) => Ok(Type::Map(
+                Box::new(self.resolve_type(key, params, span)?),
+                Box::new(self.resolve_type(value, params, span)?),
+            )),
+            other => Ok(other.clone()),
+        }
     }
 
-    Ok(())
+    fn resolve_args(&self, name: &str, args: &[Type], params: &[String], span: Span) -> Result<Vec<Type>> {
+        let type_def = self.get_type(name).ok_or_else(|| {
+            Diagnostic::error(format!("unknown type `{}`", name), span).with_primary("not declared by any `type`")
+        })?;
+        if type_def.params.len() != args.len() {
+            return Err(Diagnostic::error(
+                format!(
+                    "`{}` takes {} type argument(s) but {} were given",
+                    name,
+                    type_def.params.len(),
+                    args.len()
+                ),
+                span,
+            )
+            .with_label(type_def.span, format!("`{}` declared here", name)));
+        }
+        args.iter().map(|arg| self.resolve_type(arg, params, span)).collect()
+    }
 }
 
-fn check_handler(ctx: &TypeContext, env: &HashMap<String, Type>, handler: &Handler) -> Result<()> {
-    let mut local_env = env.clone();
+/// Agents and supervisors started with the program: those of the root file
+/// that nothing supervises, and the children of every supervisor started
+fn started(program: &Program) -> HashSet<&str> {
+    let supervised: HashSet<&str> = program.supervisors.iter().flat_map(|s| &s.children).map(|c| c.name.as_str()).collect();
+    let roots = program.agents.iter().map(|a| a.name.as_str()).chain(program.supervisors.iter().map(|s| s.name.as_str()));
+    let mut started: HashSet<&str> = roots.filter(|name| !is_imported(name) && !supervised.contains(name)).collect();
 
-    // Add handler parameters to environment (basic checking)
-    for param in &handler.params {
-        local_env.insert(param.clone(), Type::Int); // Simplified: assume Int for now
+    let mut supervisors: Vec<&SupervisorDef> = program.supervisors.iter().filter(|s| started.contains(s.name.as_str())).collect();
+    while let Some(supervisor) = supervisors.pop() {
+        for child in &supervisor.children {
+            // Cycles are reported by `check_supervisors`
+            if started.insert(&child.name) {
+                supervisors.extend(program.supervisors.iter().filter(|s| s.name == child.name));
+            }
+        }
     }
+    started
+}
+
+/// Type arguments of a generic type, by parameter name
+type Substitution = HashMap<String, Type>;
+
+/// Pair a generic type's parameters with the arguments it was given
+fn substitution(type_def: &TypeDef, args: &[Type]) -> Substitution {
+    type_def.params.iter().cloned().zip(args.iter().cloned()).collect()
+}
 
-    // Check each statement
-    for stmt in &handler.body {
-        check_stmt(ctx, &local_env, stmt)?;
+/// Replace type parameters by their arguments
+fn substitute(ty: &Type, subst: &Substitution) -> Type {
+    let all = |args: &[Type]| args.iter().map(|arg| substitute(arg, subst)).collect();
+    match ty {
+        Type::Param(name) => subst.get(name).cloned().unwrap_or_else(|| ty.clone()),
+        Type::Named(name, args) => Type::Named(name.clone(), all(args)),
+        Type::Ref(name, args) => Type::Ref(name.clone(), all(args)),
+        Type::List(elem) => Type::List(Box::new(substitute(elem, subst))),
+        Type::Option(elem) => Type::Option(Box::new(substitute(elem, subst))),
+        Type::Map(key, value) => Type::Map(Box::new(substitute(key, subst)), Box::new(substitute(value, subst))),
+        other => other.clone(),
     }
+}
 
-    Ok(())
+/// A variant with the type arguments of its type filled in
+fn instantiate(variant: &Variant, subst: &Substitution) -> Variant {
+    let mut variant = variant.clone();
+    for field in &mut variant.fields {
+        field.ty = substitute(&field.ty, subst);
+    }
+    variant
 }
 
-fn check_stmt(ctx: &TypeContext, env: &HashMap<String, Type>, stmt: &Stmt) -> Result<()> {
-    match stmt {
-        Stmt::Assign { target, value } => {
-            if !env.contains_key(target) {
-                bail!("Undefined variable: {}", target);
+/// Fill the `Infer` holes of a substitution with what a value of type
+/// `actual`, given where `declared` is expected, says the parameters are
+fn bind_params(declared: &Type, actual: &Type, subst: &mut Substitution) {
+    match (declared, actual) {
+        (Type::Param(name), actual) => {
+            if let Some(bound) = subst.get_mut(name) {
+                if let Some(unified) = unify(bound, actual) {
+                    *bound = unified;
+                }
             }
-            let _value_ty = infer_expr(env, value)?;
-            // Should check types match, but simplified for v0
-            Ok(())
         }
-        Stmt::Send {
-            target,
-            msg_variant: _,
-            args: _,
-        } => {
-            let _target_ty = infer_expr(env, target)?;
-            // Should check target is Ref[T] and variant exists in T
-            Ok(())
+        (Type::List(declared), Type::List(actual)) | (Type::Option(declared), Type::Option(actual)) => {
+            bind_params(declared, actual, subst)
         }
-        Stmt::Effect { name: _, args: _ } => {
-            // Effects are checked at runtime via capabilities
-            Ok(())
+        (Type::Map(dk, dv), Type::Map(ak, av)) => {
+            bind_params(dk, ak, subst);
+            bind_params(dv, av, subst);
         }
+        (Type::Named(d, dargs), Type::Named(a, aargs)) | (Type::Ref(d, dargs), Type::Ref(a, aargs)) if d == a => {
+            for (declared, actual) in dargs.iter().zip(aargs) {
+                bind_params(declared, actual, subst);
+            }
+        }
+        _ => {}
     }
 }
 
-fn infer_expr(env: &HashMap<String, Type>, expr: &Expr) -> Result<Type> {
-    match expr {
-        Expr::Var(name) => env
-            .get(name)
-            .cloned()
-            .ok_or_else(|| anyhow::anyhow!("Undefined variable: {}", name)),
-        Expr::Int(_) => Ok(Type::Int),
-        Expr::Str(_) => Ok(Type::String),
-        Expr::Bool(_) => Ok(Type::Bool),
-        Expr::BinOp {
-            op: _,
-            left,
-            right,
-        } => {
-            let _left_ty = infer_expr(env, left)?;
-            let _right_ty = infer_expr(env, right)?;
-            Ok(Type::Int) // Simplified
+/// A variable in scope, with where it was declared when that is known
+#[derive(Clone)]
+struct Binding {
+    ty: Type,
+    decl: Option<Span>,
+}
+
+type Vars = HashMap<String, Binding>;
+
+/// Names visible inside a handler
+#[derive(Clone, Default)]
+struct Env {
+    /// Agent state, reachable only through `state.field`
+    state: Vars,
+    /// Handler parameters and `let` bindings of enclosing blocks
+    locals: Vars,
+    /// Whether `break`/`continue` are allowed here
+    in_loop: bool,
+    /// Enclosing function, if checking a function rather than a handler
+    function: Option<String>,
+    /// Effects the enclosing function declares with `uses`
+    effects: Vec<String>,
+    /// What `return` must produce; `None` means a bare `return;`
+    returns: Option<Type>,
+    /// Type parameters of the enclosing generic agent
+    type_params: Vec<String>,
+    /// Constructor parameters of the enclosing agent, which cannot be assigned
+    params: Vars,
+    /// What `self` refers to; `None` outside handlers
+    self_ty: Option<Type>,
+}
+
+impl Env {
+    /// Whether an effect may be performed here; handlers may perform any
+    fn allows_effect(&self, effect: &str) -> bool {
+        self.function.is_none() || self.effects.iter().any(|e| e == effect)
+    }
+}
+
+/// What spawning an agent takes and produces
+#[derive(Clone)]
+struct AgentSig {
+    type_params: Vec<String>,
+    /// Constructor parameters, typed in terms of the type parameters
+    params: Vec<Param>,
+    /// Ref to the agent's protocol, in terms of its own type parameters
+    ref_ty: Type,
+    span: Span,
+}
+
+/// Walks agents and handlers, collecting diagnostics instead of stopping at the first
+struct Checker {
+    ctx: TypeContext,
+    /// Agents addressable by name, typed as refs to their protocol
+    globals: HashMap<String, Type>,
+    functions: HashMap<String, FnDef>,
+    agents: HashMap<String, AgentSig>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn report(&mut self, result: Result<()>) {
+        if let Err(diagnostic) = result {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Resolve an annotation, yielding `Type::Error` after reporting a problem
+    fn resolve(&mut self, ty: &Type, params: &[String], span: Span) -> Type {
+        match self.ctx.resolve_type(ty, params, span) {
+            Ok(ty) => ty,
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                Type::Error
+            }
         }
-        Expr::FieldAccess { obj, field: _ } => infer_expr(env, obj), // Simplified
+    }
+
+    fn declare_function(&mut self, function: &FnDef) {
+        if let Some(previous) = self.functions.get(&function.name) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("duplicate function `{}`", function.name), function.span)
+                    .with_primary("redefined here")
+                    .with_label(previous.span, "first defined here"),
+            );
+            return;
+        }
+        self.functions.insert(function.name.clone(), function.clone());
+    }
+
+    fn check_function(&mut self, function: &FnDef) {
+        let mut env = Env {
+            function: Some(function.name.clone()),
+            effects: function.effects.clone(),
+            returns: function.ret.clone(),
+            ..Env::default()
+        };
+        for param in &function.params {
+            let binding = Binding {
+                ty: self.resolve(&param.ty, &[], param.span),
+                decl: Some(param.span),
+            };
+            env.locals.insert(param.name.clone(), binding);
+        }
+        if let Some(ret) = &function.ret {
+            env.returns = Some(self.resolve(ret, &[], function.span));
+        }
+
+        self.check_block(&env, &function.body);
+
+        if let (Some(ret), false) = (&function.ret, always_returns(&function.body)) {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("function `{}` may finish without returning a value", function.name),
+                    function.span,
+                )
+                .with_primary(format!("declared to return {}", ret))
+                .with_note("every path through the body must end in `return`"),
+            );
+        }
+    }
+
+    /// Check a call's arguments and effects, returning the function's result type
+    fn check_call(&mut self, env: &Env, name: &str, args: &[Expr], span: Span) -> Result<Option<Type>> {
+        let arg_tys: Vec<Type> = args.iter().map(|arg| self.infer_expr(env, arg)).collect();
+        let function = self.functions.get(name).cloned().ok_or_else(|| {
+            Diagnostic::error(format!("unknown function `{}`", name), span)
+                .with_primary("not declared by any `fn`")
+        })?;
+        if function.params.len() != args.len() {
+            return Err(Diagnostic::error(
+                format!(
+                    "`{}` takes {} argument(s) but {} were given",
+                    name,
+                    function.params.len(),
+                    args.len()
+                ),
+                span,
+            )
+            .with_label(function.span, "declared here"));
+        }
+        for ((param, arg_ty), arg) in function.params.iter().zip(&arg_tys).zip(args) {
+            self.expect_type(&param.ty, arg_ty, arg.span, Some(param.span));
+        }
+        if let Some(caller) = &env.function {
+            for effect in function.effects.iter().filter(|e| !env.allows_effect(e)) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("`{}` uses effect `{}`, which `{}` does not declare", name, effect, caller),
+                        span,
+                    )
+                    .with_label(function.span, format!("`{}` declared here", name))
+                    .with_note(format!("add `uses {}` to the signature of `{}`", effect, caller)),
+                );
+            }
+        }
+        Ok(function.ret)
+    }
+
+    /// Report an effect performed by a function that does not declare it
+    fn check_effect(&mut self, env: &Env, effect: &str, span: Span) {
+        if let (Some(function), false) = (&env.function, env.allows_effect(effect)) {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("function `{}` performs effect `{}` without declaring it", function, effect),
+                    span,
+                )
+                .with_note(format!("add `uses {}` to the signature of `{}`", effect, function)),
+            );
+        }
+    }
+
+    fn check_agent(&mut self, agent: &AgentDef) {
+        let sig = self.agents.get(&agent.name).cloned();
+        let mut env = Env {
+            type_params: agent.type_params.clone(),
+            ..Env::default()
+        };
+        for param in sig.iter().flat_map(|sig| &sig.params) {
+            let binding = Binding {
+                ty: param.ty.clone(),
+                decl: Some(param.span),
+            };
+            env.params.insert(param.name.clone(), binding);
+        }
+
+        // Add state variables to environment; each initializer sees the ones before it
+        for state_var in &agent.state {
+            let ty = self.resolve(&state_var.ty, &agent.type_params, state_var.span);
+            let init_ty = self.infer_expr(&env, &state_var.init);
+            self.expect_type(&ty, &init_ty, state_var.init.span, Some(state_var.span));
+            let binding = Binding {
+                ty,
+                decl: Some(state_var.span),
+            };
+            env.state.insert(state_var.name.clone(), binding);
+        }
+
+        env.self_ty = sig.map(|sig| sig.ref_ty);
+
+        let protocol = self.ctx.protocol_of(agent).cloned();
+        if let (Some(name), None) = (&agent.protocol, &protocol) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("unknown type `{}`", name), agent.span)
+                    .with_primary("protocol is not declared by any `type`"),
+            );
+        }
+        if let (None, Some(handler)) = (&agent.protocol, agent.handlers.first()) {
+            let candidates = self.ctx.types_of_variant(&handler.variant);
+            if candidates.len() > 1 {
+                let mut diagnostic = Diagnostic::error(
+                    format!("cannot infer the protocol of `{}`", agent.name),
+                    handler.span,
+                )
+                .with_primary(format!("`{}` is declared by {} types", handler.variant, candidates.len()));
+                for candidate in &candidates {
+                    diagnostic = diagnostic.with_label(candidate.span, format!("declared by `{}`", candidate.name));
+                }
+                self.diagnostics.push(diagnostic.with_note(format!(
+                    "declare it with `agent {} : {}`",
+                    agent.name, candidates[0].name
+                )));
+            }
+        }
+
+        // Handlers see the protocol's fields with its type arguments filled in
+        let subst = match &protocol {
+            Some(protocol) => {
+                // Problems with the arguments were reported along with the agent's ref type
+                let args = self.ctx.protocol_args(agent, protocol);
+                substitution(protocol, &args.unwrap_or_else(|_| vec![Type::Error; protocol.params.len()]))
+            }
+            None => Substitution::new(),
+        };
+
+        // Check each handler
+        let mut seen: HashMap<&str, Span> = HashMap::new();
+        for handler in &agent.handlers {
+            if let Some(first) = seen.get(handler.variant.as_str()) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("duplicate handler for `{}` in agent `{}`", handler.variant, agent.name),
+                        handler.span,
+                    )
+                    .with_label(*first, "first handled here"),
+                );
+            }
+            seen.entry(&handler.variant).or_insert(handler.span);
+            self.check_handler(&env, agent, protocol.as_ref(), &subst, handler);
+        }
+
+        // Hooks run like handlers of a message without fields
+        for (i, hook) in agent.hooks.iter().enumerate() {
+            if let Some(first) = agent.hooks[..i].iter().find(|h| h.event == hook.event) {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("duplicate `on {}` in agent `{}`", hook.event, agent.name), hook.span)
+                        .with_label(first.span, "first declared here"),
+                );
+            }
+            self.check_block(&env, &hook.body);
+        }
+
+        // A declared protocol must be handled exhaustively
+        if let (Some(_), Some(protocol)) = (&agent.protocol, &protocol) {
+            let missing: Vec<String> = protocol
+                .variants
+                .iter()
+                .filter(|v| !seen.contains_key(v.name.as_str()))
+                .map(|v| format!("`{}`", v.name))
+                .collect();
+            if !missing.is_empty() {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("agent `{}` has no handler for {}", agent.name, missing.join(", ")),
+                        agent.span,
+                    )
+                    .with_label(protocol.span, format!("`{}` declared here", protocol.name)),
+                );
+            }
+        }
+    }
+
+    /// Supervisors must form a tree whose leaves are agents started without arguments
+    fn check_supervisors(&mut self, supervisors: &[SupervisorDef]) {
+        let mut declared: HashMap<&str, Span> = HashMap::new();
+        for supervisor in supervisors {
+            let previous = declared.get(supervisor.name.as_str()).copied();
+            if let Some(previous) = previous.or_else(|| self.agents.get(&supervisor.name).map(|a| a.span)) {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("duplicate definition of `{}`", supervisor.name), supervisor.span)
+                        .with_primary("redefined here")
+                        .with_label(previous, "first defined here"),
+                );
+                continue;
+            }
+            declared.insert(&supervisor.name, supervisor.span);
+        }
+
+        let mut parents: HashMap<&str, &str> = HashMap::new();
+        for supervisor in supervisors {
+            for child in &supervisor.children {
+                if let Some(sig) = self.agents.get(&child.name) {
+                    if !sig.params.is_empty() {
+                        self.diagnostics.push(
+                            Diagnostic::error(format!("cannot supervise `{}`", child.name), child.span)
+                                .with_primary("this agent takes constructor parameters")
+                                .with_label(sig.span, "declared here")
+                                .with_note("supervised agents are started without arguments"),
+                        );
+                    }
+                } else if !declared.contains_key(child.name.as_str()) {
+                    self.diagnostics.push(
+                        Diagnostic::error(format!("unknown agent or supervisor `{}`", child.name), child.span)
+                            .with_primary("not declared by any `agent` or `supervisor`"),
+                    );
+                    continue;
+                }
+                if let Some(parent) = parents.insert(&child.name, &supervisor.name) {
+                    self.diagnostics.push(
+                        Diagnostic::error(format!("`{}` is supervised twice", child.name), child.span)
+                            .with_primary(format!("already a child of `{}`", parent))
+                            .with_note("each agent or supervisor has at most one supervisor"),
+                    );
+                }
+            }
+        }
+
+        // With one parent each, a cycle is found by walking up from any of its
+        // members; it is reported once, at its first declared supervisor
+        for supervisor in supervisors {
+            let mut cycle = vec![supervisor.name.as_str()];
+            while let Some(&parent) = parents.get(cycle[cycle.len() - 1]) {
+                if parent == supervisor.name {
+                    let first = supervisors.iter().position(|s| cycle.contains(&s.name.as_str()));
+                    if first.is_some_and(|i| supervisors[i].name == supervisor.name) {
+                        cycle.push(parent);
+                        // Walked child to parent; shown parent to child
+                        let path: Vec<&str> = cycle.into_iter().rev().collect();
+                        self.diagnostics.push(
+                            Diagnostic::error(format!("supervisor `{}` supervises itself", supervisor.name), supervisor.span)
+                                .with_note(format!("cycle: {}", path.join(" -> "))),
+                        );
+                    }
+                    break;
+                }
+                if cycle.contains(&parent) || cycle.len() > supervisors.len() {
+                    break;
+                }
+                cycle.push(parent);
+            }
+        }
+    }
+
+    fn check_handler(
+        &mut self,
+        env: &Env,
+        agent: &AgentDef,
+        protocol: Option<&TypeDef>,
+        subst: &Substitution,
+        handler: &Handler,
+    ) {
+        let mut local_env = env.clone();
+
+        let variant = protocol.and_then(|p| p.variants.iter().find(|v| v.name == handler.variant));
+        let variant = match (protocol, variant) {
+            (_, Some(variant)) => Some(instantiate(variant, subst)),
+            (Some(protocol), None) => {
+                let mut diag = Diagnostic::error(
+                    format!("`{}` is not a variant of `{}`", handler.variant, protocol.name),
+                    handler.span,
+                )
+                .with_label(protocol.span, format!("`{}` declared here", protocol.name));
+                if agent.protocol.is_none() {
+                    diag = diag.with_note(format!(
+                        "the protocol of `{}` is inferred from its first handler; declare it with `agent {} : Type`",
+                        agent.name, agent.name
+                    ));
+                }
+                self.diagnostics.push(diag);
+                None
+            }
+            (None, None) => {
+                // An unknown declared protocol is reported by the caller
+                if agent.protocol.is_none() {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!("handler for unknown variant `{}`", handler.variant),
+                            handler.span,
+                        )
+                        .with_primary("no message type declares this variant"),
+                    );
+                }
+                None
+            }
+        };
+
+        // Handler parameters take the types of the variant's fields
+        self.bind_fields(&mut local_env, variant.as_ref(), &handler.params, handler.span);
+
+        self.check_block(&local_env, &handler.body);
+    }
+
+    /// Bind destructured field names as locals typed by the variant's fields
+    fn bind_fields(&mut self, env: &mut Env, variant: Option<&Variant>, names: &[String], span: Span) {
+        for name in names {
+            let field = variant.and_then(|v| v.fields.iter().find(|f| &f.name == name));
+            let binding = match (variant, field) {
+                (_, Some(field)) => Binding {
+                    ty: field.ty.clone(),
+                    decl: Some(field.span),
+                },
+                (Some(variant), None) => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!("variant `{}` has no field `{}`", variant.name, name),
+                            span,
+                        )
+                        .with_label(variant.span, "variant declared here"),
+                    );
+                    Binding {
+                        ty: Type::Error,
+                        decl: None,
+                    }
+                }
+                // Unknown variant, already reported
+                (None, None) => Binding {
+                    ty: Type::Error,
+                    decl: None,
+                },
+            };
+            env.locals.insert(name.clone(), binding);
+        }
+    }
+
+    /// Check a block in its own scope; its `let`s are dropped at the end
+    fn check_block(&mut self, env: &Env, body: &[Stmt]) {
+        let mut scope = env.clone();
+        for stmt in body {
+            self.check_stmt(&mut scope, stmt);
+        }
+    }
+
+    fn check_stmt(&mut self, env: &mut Env, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, ty, value } => {
+                let value_ty = self.infer_expr(env, value);
+                let ty = match ty {
+                    Some(ty) => {
+                        let ty = self.resolve(ty, &env.type_params, stmt.span);
+                        self.expect_type(&ty, &value_ty, value.span, Some(stmt.span));
+                        ty
+                    }
+                    None if value_ty.has_holes() => {
+                        self.diagnostics.push(
+                            Diagnostic::error("type annotations needed", value.span)
+                                .with_primary(format!("cannot infer the full type of {}", value_ty))
+                                .with_note(format!("annotate it, e.g. `let {}: {} = ...`", name, fill_holes(&value_ty))),
+                        );
+                        Type::Error
+                    }
+                    None => value_ty,
+                };
+                let binding = Binding {
+                    ty,
+                    decl: Some(stmt.span),
+                };
+                env.locals.insert(name.clone(), binding);
+            }
+            StmtKind::Assign { target, value } => {
+                let value_ty = self.infer_expr(env, value);
+                let binding = match target {
+                    AssignTarget::State(field) => {
+                        env.state.get(field).ok_or_else(|| unknown_state(env, field, stmt.span))
+                    }
+                    AssignTarget::Var(name) => match env.locals.get(name) {
+                        Some(binding) => Ok(binding),
+                        None if env.params.contains_key(name) => Err(Diagnostic::error(
+                            format!("cannot assign to constructor parameter `{}`", name),
+                            stmt.span,
+                        )
+                        .with_note("copy it into a `state` field to change it")),
+                        None if self.globals.contains_key(name) => Err(Diagnostic::error(
+                            format!("cannot assign to agent `{}`", name),
+                            stmt.span,
+                        )),
+                        None => Err(undefined_variable(name, stmt.span)),
+                    },
+                };
+                match binding {
+                    Ok(binding) => self.expect_type(&binding.ty, &value_ty, value.span, binding.decl),
+                    Err(diagnostic) => self.diagnostics.push(diagnostic),
+                }
+            }
+            StmtKind::Send {
+                target,
+                msg_variant,
+                args,
+            } => {
+                self.check_effect(env, "send", stmt.span);
+                match self.target_variant(env, target, msg_variant, stmt.span) {
+                    Some((variant, mut subst)) => self.check_fields(env, &variant, args, stmt.span, &mut subst),
+                    // Still check the arguments themselves
+                    None => {
+                        for (_, arg) in args {
+                            self.infer_expr(env, arg);
+                        }
+                    }
+                }
+            }
+            StmtKind::Effect { name, args } if self.functions.contains_key(name) => {
+                let result = self.check_call(env, name, args, stmt.span);
+                if let Err(diagnostic) = result {
+                    self.diagnostics.push(diagnostic);
+                }
+            }
+            StmtKind::Effect { name, args } => {
+                // Effects are checked at runtime via capabilities
+                self.check_effect(env, name, stmt.span);
+                for arg in args {
+                    self.infer_expr(env, arg);
+                }
+            }
+            StmtKind::Return(value) => {
+                let value_ty = value.as_ref().map(|value| self.infer_expr(env, value));
+                match (&env.returns, value, value_ty) {
+                    (Some(expected), Some(value), Some(actual)) => {
+                        self.expect_type(expected, &actual, value.span, None)
+                    }
+                    (Some(expected), None, _) => self.diagnostics.push(
+                        Diagnostic::error("missing return value", stmt.span)
+                            .with_primary(format!("expected a value of type {}", expected)),
+                    ),
+                    (None, Some(value), _) => {
+                        let context = match &env.function {
+                            Some(function) => format!("function `{}` has no return type", function),
+                            None => "handlers cannot return a value".to_string(),
+                        };
+                        self.diagnostics.push(
+                            Diagnostic::error("unexpected return value", value.span).with_primary(context),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            StmtKind::If {
+                cond,
+                then_body,
+                else_body,
+            } => {
+                let cond_ty = self.infer_expr(env, cond);
+                self.expect_type(&Type::Bool, &cond_ty, cond.span, None);
+                self.check_block(env, then_body);
+                self.check_block(env, else_body);
+            }
+            StmtKind::Match { scrutinee, arms } => self.check_match(env, scrutinee, arms),
+            StmtKind::While { cond, body } => {
+                let cond_ty = self.infer_expr(env, cond);
+                self.expect_type(&Type::Bool, &cond_ty, cond.span, None);
+                let loop_env = Env {
+                    in_loop: true,
+                    ..env.clone()
+                };
+                self.check_block(&loop_env, body);
+            }
+            StmtKind::For { var, iter, body } => {
+                let var_ty = match iter {
+                    ForIter::Range { start, end } => {
+                        for bound in [start, end] {
+                            let bound_ty = self.infer_expr(env, bound);
+                            self.expect_type(&Type::Int, &bound_ty, bound.span, None);
+                        }
+                        Type::Int
+                    }
+                    ForIter::List(list) => match self.infer_expr(env, list) {
+                        Type::List(elem) => *elem,
+                        Type::Error => Type::Error,
+                        other => {
+                            self.diagnostics.push(
+                                Diagnostic::error(format!("cannot iterate over a value of type {}", other), list.span)
+                                    .with_primary("expected a List or a range `start..end`"),
+                            );
+                            Type::Error
+                        }
+                    },
+                };
+                let mut loop_env = Env {
+                    in_loop: true,
+                    ..env.clone()
+                };
+                let binding = Binding {
+                    ty: var_ty,
+                    decl: Some(stmt.span),
+                };
+                loop_env.locals.insert(var.clone(), binding);
+                self.check_block(&loop_env, body);
+            }
+            StmtKind::Break | StmtKind::Continue if !env.in_loop => {
+                let keyword = if matches!(stmt.kind, StmtKind::Break) { "break" } else { "continue" };
+                self.diagnostics.push(
+                    Diagnostic::error(format!("`{}` outside of a loop", keyword), stmt.span)
+                        .with_primary("not inside `while` or `for`"),
+                );
+            }
+            StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::Expr(expr) => match &expr.kind {
+                ExprKind::MethodCall { receiver, method, args } => {
+                    let receiver_ty = self.infer_expr(env, receiver);
+                    match self.check_method(env, &receiver_ty, method, args, expr.span) {
+                        // Mutating methods write the result back, so need a place to write to
+                        Ok(None) if !is_place(env, receiver) => self.diagnostics.push(
+                            Diagnostic::error(format!("cannot call `{}` on a temporary value", method), receiver.span)
+                                .with_primary("expected a local variable or `state.field`"),
+                        ),
+                        Ok(_) => {}
+                        Err(diagnostic) => self.diagnostics.push(diagnostic),
+                    }
+                }
+                _ => {
+                    self.infer_expr(env, expr);
+                }
+            },
+        }
+    }
+
+    /// The variant a message sent to `target` has, with the type arguments of
+    /// the target's protocol
+    fn target_variant(
+        &mut self,
+        env: &Env,
+        target: &Expr,
+        msg_variant: &str,
+        span: Span,
+    ) -> Option<(Variant, Substitution)> {
+        let target_ty = self.infer_expr(env, target);
+        match &target_ty {
+            Type::Ref(msg_type, args) if self.ctx.variant_exists(msg_type, msg_variant) => {
+                let type_def = self.ctx.get_type(msg_type).expect("the variant exists");
+                let subst = substitution(type_def, args);
+                self.ctx.get_variant(msg_type, msg_variant).cloned().map(|v| (v, subst))
+            }
+            Type::Ref(msg_type, _) => {
+                let mut diag = Diagnostic::error(format!("unknown variant `{}` for {}", msg_variant, target_ty), span);
+                if let Some(type_def) = self.ctx.get_type(msg_type) {
+                    diag = diag.with_label(type_def.span, format!("`{}` declared here", msg_type));
+                }
+                self.diagnostics.push(diag);
+                None
+            }
+            Type::Error => None,
+            other => {
+                self.diagnostics.push(
+                    Diagnostic::error("cannot send to a non-ref value", target.span)
+                        .with_primary(format!("this has type {}", other)),
+                );
+                None
+            }
+        }
+    }
+
+    /// Check an `ask`, returning the type of the reply it waits for
+    fn check_ask(
+        &mut self,
+        env: &Env,
+        target: &Expr,
+        msg_variant: &str,
+        args: &[(String, Expr)],
+        timeout: &Expr,
+        span: Span,
+    ) -> Result<Type> {
+        self.check_effect(env, "send", span);
+        // The request would only be handled once the asking handler is done
+        if matches!(target.kind, ExprKind::SelfRef) {
+            return Err(Diagnostic::error("an agent cannot `ask` itself", target.span)
+                .with_primary("this handler would wait for itself until the timeout")
+                .with_note("use `send self` instead"));
+        }
+        let Some((mut variant, mut subst)) = self.target_variant(env, target, msg_variant, span) else {
+            for (_, arg) in args {
+                self.infer_expr(env, arg);
+            }
+            return Ok(Type::Error);
+        };
+
+        if let Some((_, given)) = args.iter().find(|(name, _)| name == REPLY_FIELD) {
+            self.infer_expr(env, given);
+            return Err(Diagnostic::error(format!("`{}` is filled in by `ask`", REPLY_FIELD), given.span)
+                .with_primary("remove this field"));
+        }
+        let reply = variant.fields.iter().position(|f| f.name == REPLY_FIELD);
+        let Some(reply) = reply.filter(|&i| matches!(variant.fields[i].ty, Type::Ref(..))) else {
+            for (_, arg) in args {
+                self.infer_expr(env, arg);
+            }
+            return Err(
+                Diagnostic::error(format!("`{}` cannot be asked for a reply", variant.name), span)
+                    .with_label(variant.span, "variant declared here")
+                    .with_note(format!("`ask` needs a `{}: Ref[..]` field to send the reply to", REPLY_FIELD)),
+            );
+        };
+        let reply = variant.fields.remove(reply);
+        self.check_fields(env, &variant, args, span, &mut subst);
+
+        let timeout_ty = self.infer_expr(env, timeout);
+        self.expect_type(&Type::Duration, &timeout_ty, timeout.span, None);
+
+        // Whatever is sent to the reply ref is a message of its type
+        let Type::Ref(name, type_args) = substitute(&reply.ty, &subst) else {
+            unreachable!("substitution keeps a ref a ref");
+        };
+        Ok(Type::Option(Box::new(Type::Named(name, type_args))))
+    }
+
+    /// Check a built-in method call, returning its result type (`None` if it
+    /// updates the receiver in place)
+    fn check_method(
+        &mut self,
+        env: &Env,
+        receiver_ty: &Type,
+        method: &str,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<Option<Type>> {
+        let arg_tys: Vec<Type> = args.iter().map(|arg| self.infer_expr(env, arg)).collect();
+        if *receiver_ty == Type::Error {
+            return Ok(Some(Type::Error));
+        }
+        let (params, ret) = method_signature(receiver_ty, method).ok_or_else(|| {
+            Diagnostic::error(format!("no method `{}` on type {}", method, receiver_ty), span)
+        })?;
+        if params.len() != args.len() {
+            return Err(Diagnostic::error(
+                format!(
+                    "`{}` takes {} argument(s) but {} were given",
+                    method,
+                    params.len(),
+                    args.len()
+                ),
+                span,
+            ));
+        }
+        for ((param, arg_ty), arg) in params.iter().zip(&arg_tys).zip(args) {
+            self.expect_type(param, arg_ty, arg.span, None);
+        }
+        Ok(ret)
+    }
+
+    fn check_match(&mut self, env: &Env, scrutinee: &Expr, arms: &[MatchArm]) {
+        let scrutinee_ty = self.infer_expr(env, scrutinee);
+        let mut option_of = None;
+        let mut subst = Substitution::new();
+        let type_def = match &scrutinee_ty {
+            Type::Named(name, args) => {
+                let type_def = self.ctx.get_type(name).cloned();
+                if let Some(type_def) = &type_def {
+                    subst = substitution(type_def, args);
+                }
+                type_def
+            }
+            Type::Option(elem) => {
+                option_of = Some((**elem).clone());
+                None
+            }
+            Type::Error => None,
+            other => {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("cannot match on a value of type {}", other), scrutinee.span)
+                        .with_primary("only message types and options can be matched"),
+                );
+                None
+            }
+        };
+
+        let mut covered: HashMap<&str, Span> = HashMap::new();
+        let mut wildcard: Option<Span> = None;
+        for arm in arms {
+            if let Some(wildcard) = wildcard {
+                self.diagnostics.push(
+                    Diagnostic::error("unreachable match arm", arm.span)
+                        .with_label(wildcard, "everything is already matched here"),
+                );
+            }
+
+            let mut arm_env = env.clone();
+            match &arm.pattern {
+                Pattern::Variant { name, bindings } if option_of.is_some() => {
+                    self.diagnostics.push(
+                        Diagnostic::error(format!("`{}` is not a pattern for {}", name, scrutinee_ty), arm.span)
+                            .with_primary("expected `some(x)` or `none`"),
+                    );
+                    self.bind_fields(&mut arm_env, None, bindings, arm.span);
+                }
+                Pattern::Variant { name, bindings } => {
+                    let variant = type_def
+                        .as_ref()
+                        .and_then(|t| t.variants.iter().find(|v| &v.name == name))
+                        .map(|v| instantiate(v, &subst));
+                    if let (Some(type_def), None) = (&type_def, &variant) {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                format!("`{}` is not a variant of `{}`", name, type_def.name),
+                                arm.span,
+                            )
+                            .with_label(type_def.span, format!("`{}` declared here", type_def.name)),
+                        );
+                    }
+                    if let Some(first) = covered.get(name.as_str()) {
+                        self.diagnostics.push(
+                            Diagnostic::error("unreachable match arm", arm.span)
+                                .with_label(*first, format!("`{}` is already matched here", name)),
+                        );
+                    }
+                    covered.entry(name).or_insert(arm.span);
+                    self.bind_fields(&mut arm_env, variant.as_ref(), bindings, arm.span);
+                }
+                Pattern::Some(_) | Pattern::None => {
+                    let (key, binding) = match &arm.pattern {
+                        Pattern::Some(binding) => ("some(_)", Some(binding)),
+                        _ => ("none", None),
+                    };
+                    let elem_ty = match (&option_of, &scrutinee_ty) {
+                        (Some(elem), _) => elem.clone(),
+                        (None, Type::Error) => Type::Error,
+                        (None, _) => {
+                            self.diagnostics.push(
+                                Diagnostic::error(format!("`{}` is not a pattern for {}", key, scrutinee_ty), arm.span)
+                                    .with_primary("only options match `some` and `none`"),
+                            );
+                            Type::Error
+                        }
+                    };
+                    if let Some(first) = covered.get(key) {
+                        self.diagnostics.push(
+                            Diagnostic::error("unreachable match arm", arm.span)
+                                .with_label(*first, format!("`{}` is already matched here", key)),
+                        );
+                    }
+                    covered.entry(key).or_insert(arm.span);
+                    if let Some(binding) = binding {
+                        let binding_ty = Binding {
+                            ty: elem_ty,
+                            decl: Some(arm.span),
+                        };
+                        arm_env.locals.insert(binding.clone(), binding_ty);
+                    }
+                }
+                Pattern::Wildcard => wildcard = wildcard.or(Some(arm.span)),
+            }
+            self.check_block(&arm_env, &arm.body);
+        }
+
+        if let (Some(type_def), None) = (&type_def, wildcard) {
+            let missing: Vec<String> = type_def
+                .variants
+                .iter()
+                .filter(|v| !covered.contains_key(v.name.as_str()))
+                .map(|v| format!("`{}`", v.name))
+                .collect();
+            if !missing.is_empty() {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("non-exhaustive match: {} not covered", missing.join(", ")),
+                        scrutinee.span,
+                    )
+                    .with_label(type_def.span, format!("`{}` declared here", type_def.name))
+                    .with_note("add the missing arms or a `_` arm"),
+                );
+            }
+        }
+
+        if let (Some(_), None) = (&option_of, wildcard) {
+            let missing: Vec<&str> = ["some(_)", "none"]
+                .into_iter()
+                .filter(|key| !covered.contains_key(key))
+                .collect();
+            if !missing.is_empty() {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("non-exhaustive match: `{}` not covered", missing.join("`, `")),
+                        scrutinee.span,
+                    )
+                    .with_note("add the missing arms or a `_` arm"),
+                );
+            }
+        }
+    }
+
+    /// Check message arguments against a variant's declared fields, inferring
+    /// the type arguments still missing from `subst`
+    fn check_fields(
+        &mut self,
+        env: &Env,
+        variant: &Variant,
+        args: &[(String, Expr)],
+        span: Span,
+        subst: &mut Substitution,
+    ) {
+        for (i, (name, arg)) in args.iter().enumerate() {
+            let arg_ty = self.infer_expr(env, arg);
+            if let Some((_, prev)) = args[..i].iter().find(|(prev, _)| prev == name) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("field `{}` given twice in `{}`", name, variant.name),
+                        arg.span,
+                    )
+                    .with_label(prev.span, "first given here"),
+                );
+                continue;
+            }
+            match variant.fields.iter().find(|f| &f.name == name) {
+                Some(field) => {
+                    bind_params(&field.ty, &arg_ty, subst);
+                    let expected = substitute(&field.ty, subst);
+                    self.expect_type(&expected, &arg_ty, arg.span, Some(field.span))
+                }
+                None => self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("variant `{}` has no field `{}`", variant.name, name),
+                        arg.span,
+                    )
+                    .with_label(variant.span, "variant declared here"),
+                ),
+            }
+        }
+
+        for field in &variant.fields {
+            if !args.iter().any(|(name, _)| name == &field.name) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("missing field `{}` in `{}`", field.name, variant.name),
+                        span,
+                    )
+                    .with_label(field.span, "field declared here"),
+                );
+            }
+        }
+    }
+
+    fn expect_type(&mut self, expected: &Type, actual: &Type, span: Span, decl: Option<Span>) {
+        // An error type has already been reported; don't cascade
+        if unify(expected, actual).is_some() {
+            return;
+        }
+        let mut diag = Diagnostic::error("mismatched types", span)
+            .with_primary(format!("expected {}, found {}", expected, actual));
+        if let Some(decl) = decl {
+            diag = diag.with_label(decl, format!("declared as {} here", expected));
+        }
+        self.diagnostics.push(diag);
+    }
+
+    /// Infer an expression's type, yielding `Type::Error` after reporting a problem
+    fn infer_expr(&mut self, env: &Env, expr: &Expr) -> Type {
+        match self.try_infer_expr(env, expr) {
+            Ok(ty) => ty,
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                Type::Error
+            }
+        }
+    }
+
+    fn try_infer_expr(&mut self, env: &Env, expr: &Expr) -> Result<Type> {
+        match &expr.kind {
+            // Locals shadow agent names
+            ExprKind::Var(name) => env
+                .locals
+                .get(name)
+                .or_else(|| env.params.get(name))
+                .map(|binding| binding.ty.clone())
+                .or_else(|| self.globals.get(name).cloned())
+                .ok_or_else(|| match self.agents.get(name) {
+                    Some(sig) => {
+                        let reason = if sig.params.is_empty() {
+                            "it is not started with the program, so is only started by `spawn`"
+                        } else {
+                            "it takes constructor parameters, so is only started by `spawn`"
+                        };
+                        Diagnostic::error(format!("agent `{}` has no instance to refer to by name", name), expr.span)
+                            .with_label(sig.span, reason)
+                            .with_note(format!("use the ref returned by `spawn {}(..)`", name))
+                    }
+                    None => undefined_variable(name, expr.span),
+                }),
+            ExprKind::StateField(field) => env
+                .state
+                .get(field)
+                .map(|binding| binding.ty.clone())
+                .ok_or_else(|| unknown_state(env, field, expr.span)),
+            ExprKind::Int(_) => Ok(Type::Int),
+            ExprKind::Float(_) => Ok(Type::Float),
+            ExprKind::Duration(_) => Ok(Type::Duration),
+            ExprKind::Str(_) => Ok(Type::String),
+            ExprKind::Interpolate(parts) => {
+                for part in parts {
+                    let StrPart::Expr(part) = part else { continue };
+                    match self.infer_expr(env, part) {
+                        Type::Int | Type::Float | Type::String | Type::Bool | Type::Duration | Type::Error => {}
+                        other => self.diagnostics.push(
+                            Diagnostic::error(format!("cannot interpolate a value of type {}", other), part.span)
+                                .with_primary("only Int, Float, String, Bool and Duration can be interpolated"),
+                        ),
+                    }
+                }
+                Ok(Type::String)
+            }
+            ExprKind::Bool(_) => Ok(Type::Bool),
+            ExprKind::BinOp { op, left, right } => {
+                let left_ty = self.infer_expr(env, left);
+                let right_ty = self.infer_expr(env, right);
+                if left_ty == Type::Error || right_ty == Type::Error {
+                    return Ok(Type::Error);
+                }
+                infer_binop(op, &left_ty, &right_ty).ok_or_else(|| {
+                    Diagnostic::error(
+                        format!("operator `{}` cannot be applied to {} and {}", op, left_ty, right_ty),
+                        expr.span,
+                    )
+                    .with_label(left.span, format!("this is {}", left_ty))
+                    .with_label(right.span, format!("this is {}", right_ty))
+                })
+            }
+            ExprKind::Unary { op, operand } => {
+                let operand_ty = self.infer_expr(env, operand);
+                if operand_ty == Type::Error {
+                    return Ok(Type::Error);
+                }
+                infer_unary(op, &operand_ty).ok_or_else(|| {
+                    Diagnostic::error(
+                        format!("operator `{}` cannot be applied to {}", op, operand_ty),
+                        expr.span,
+                    )
+                    .with_label(operand.span, format!("this is {}", operand_ty))
+                })
+            }
+            ExprKind::FieldAccess { obj, field } => {
+                let obj_ty = self.infer_expr(env, obj);
+                let no_field = || {
+                    Diagnostic::error(format!("no field `{}` on type {}", field, obj_ty), expr.span)
+                };
+                let (type_name, args) = match &obj_ty {
+                    Type::Named(type_name, args) => (type_name, args),
+                    Type::Error => return Ok(Type::Error),
+                    _ => return Err(no_field()),
+                };
+                // Only fields shared by every variant are statically known to exist
+                let type_def = self.ctx.get_type(type_name).ok_or_else(no_field)?;
+                let mut field_ty = None;
+                for variant in &type_def.variants {
+                    let ty = variant
+                        .fields
+                        .iter()
+                        .find(|f| &f.name == field)
+                        .map(|f| &f.ty)
+                        .filter(|ty| field_ty.is_none() || field_ty == Some(*ty));
+                    match ty {
+                        Some(ty) => field_ty = Some(ty),
+                        None => {
+                            return Err(no_field()
+                                .with_label(variant.span, "not present with this type in this variant"))
+                        }
+                    }
+                }
+                let field_ty = field_ty.ok_or_else(no_field)?;
+                Ok(substitute(field_ty, &substitution(type_def, args)))
+            }
+            ExprKind::List(items) => {
+                let mut elem_ty = Type::Infer;
+                for item in items {
+                    let item_ty = self.infer_expr(env, item);
+                    match unify(&elem_ty, &item_ty) {
+                        Some(ty) => elem_ty = ty,
+                        None => self.expect_type(&elem_ty, &item_ty, item.span, None),
+                    }
+                }
+                Ok(Type::List(Box::new(elem_ty)))
+            }
+            ExprKind::Map(entries) => {
+                let (mut key_ty, mut value_ty) = (Type::Infer, Type::Infer);
+                for (key, value) in entries {
+                    for (expr, ty) in [(key, &mut key_ty), (value, &mut value_ty)] {
+                        let actual = self.infer_expr(env, expr);
+                        match unify(ty, &actual) {
+                            Some(unified) => *ty = unified,
+                            None => self.expect_type(ty, &actual, expr.span, None),
+                        }
+                    }
+                }
+                Ok(Type::Map(Box::new(key_ty), Box::new(value_ty)))
+            }
+            ExprKind::Some(value) => Ok(Type::Option(Box::new(self.infer_expr(env, value)))),
+            ExprKind::None => Ok(Type::Option(Box::new(Type::Infer))),
+            ExprKind::Index { obj, index } => {
+                let obj_ty = self.infer_expr(env, obj);
+                let index_ty = self.infer_expr(env, index);
+                let (key_ty, elem_ty) = match &obj_ty {
+                    Type::List(elem) => (Type::Int, (**elem).clone()),
+                    Type::Map(key, value) => ((**key).clone(), (**value).clone()),
+                    Type::Error => return Ok(Type::Error),
+                    other => {
+                        return Err(Diagnostic::error(format!("cannot index into a value of type {}", other), expr.span)
+                            .with_label(obj.span, format!("this is {}", other)))
+                    }
+                };
+                self.expect_type(&key_ty, &index_ty, index.span, None);
+                Ok(elem_ty)
+            }
+            ExprKind::Ask {
+                target,
+                msg_variant,
+                args,
+                timeout,
+            } => self.check_ask(env, target, msg_variant, args, timeout, expr.span),
+            ExprKind::Schedule {
+                delay,
+                repeat: _,
+                target,
+                msg_variant,
+                args,
+            } => {
+                self.check_effect(env, "send", expr.span);
+                let delay_ty = self.infer_expr(env, delay);
+                self.expect_type(&Type::Duration, &delay_ty, delay.span, None);
+                match self.target_variant(env, target, msg_variant, expr.span) {
+                    Some((variant, mut subst)) => self.check_fields(env, &variant, args, expr.span, &mut subst),
+                    None => {
+                        for (_, arg) in args {
+                            self.infer_expr(env, arg);
+                        }
+                    }
+                }
+                Ok(Type::Timer)
+            }
+            ExprKind::SelfRef => env.self_ty.clone().ok_or_else(|| {
+                Diagnostic::error("`self` outside of a handler", expr.span)
+                    .with_primary("only handlers run as an agent")
+            }),
+            ExprKind::Spawn { agent, type_args, args } => {
+                self.check_effect(env, "spawn", expr.span);
+                let arg_tys: Vec<Type> = args.iter().map(|arg| self.infer_expr(env, arg)).collect();
+                let sig = self.agents.get(agent).cloned().ok_or_else(|| {
+                    Diagnostic::error(format!("unknown agent `{}`", agent), expr.span)
+                        .with_primary("not declared by any `agent`")
+                })?;
+                let type_arg_tys = match type_args.len() {
+                    // Inferred from how the ref is used, as for agents named directly
+                    0 => vec![Type::Infer; sig.type_params.len()],
+                    n if n == sig.type_params.len() => type_args
+                        .iter()
+                        .map(|arg| self.ctx.resolve_type(arg, &env.type_params, expr.span))
+                        .collect::<Result<_>>()?,
+                    n => {
+                        return Err(Diagnostic::error(
+                            format!(
+                                "`{}` takes {} type argument(s) but {} were given",
+                                agent,
+                                sig.type_params.len(),
+                                n
+                            ),
+                            expr.span,
+                        )
+                        .with_label(sig.span, format!("`{}` declared here", agent)))
+                    }
+                };
+                let mut subst: Substitution = sig.type_params.iter().cloned().zip(type_arg_tys).collect();
+                if sig.params.len() != args.len() {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "`{}` takes {} argument(s) but {} were given",
+                            agent,
+                            sig.params.len(),
+                            args.len()
+                        ),
+                        expr.span,
+                    )
+                    .with_label(sig.span, format!("`{}` declared here", agent)));
+                }
+                for ((param, arg_ty), arg) in sig.params.iter().zip(&arg_tys).zip(args) {
+                    bind_params(&param.ty, arg_ty, &mut subst);
+                    let expected = substitute(&param.ty, &subst);
+                    self.expect_type(&expected, arg_ty, arg.span, Some(param.span));
+                }
+                Ok(substitute(&sig.ref_ty, &subst))
+            }
+            ExprKind::Call { name, args } => self.check_call(env, name, args, expr.span)?.ok_or_else(|| {
+                Diagnostic::error(format!("`{}` does not return a value", name), expr.span)
+                    .with_primary("it has no return type; call it as a statement")
+            }),
+            ExprKind::MethodCall { receiver, method, args } => {
+                let receiver_ty = self.infer_expr(env, receiver);
+                self.check_method(env, &receiver_ty, method, args, expr.span)?
+                    .ok_or_else(|| {
+                        Diagnostic::error(format!("`{}` does not produce a value", method), expr.span)
+                            .with_primary("it updates the collection in place; call it as a statement")
+                    })
+            }
+            ExprKind::Construct {
+                type_name,
+                variant,
+                fields,
+            } => {
+                let type_def = self.ctx.get_type(type_name).cloned().ok_or_else(|| {
+                    Diagnostic::error(format!("unknown type `{}`", type_name), expr.span)
+                        .with_primary("not declared by any `type`")
+                })?;
+                let Some(variant_def) = type_def.variants.iter().find(|v| &v.name == variant) else {
+                    return Err(Diagnostic::error(
+                        format!("`{}` is not a variant of `{}`", variant, type_name),
+                        expr.span,
+                    )
+                    .with_label(type_def.span, format!("`{}` declared here", type_name)));
+                };
+                // Type arguments are inferred from the fields
+                let mut subst = type_def.params.iter().map(|p| (p.clone(), Type::Infer)).collect();
+                self.check_fields(env, variant_def, fields, expr.span, &mut subst);
+                let args = type_def.params.iter().map(|p| subst[p].clone()).collect();
+                Ok(Type::Named(type_name.clone(), args))
+            }
+        }
+    }
+}
+
+fn undefined_variable(name: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(format!("undefined variable `{}`", name), span)
+        .with_primary("not found in this scope")
+}
+
+fn unknown_state(env: &Env, field: &str, span: Span) -> Diagnostic {
+    match &env.function {
+        Some(function) => Diagnostic::error(format!("function `{}` cannot access agent state", function), span)
+            .with_primary("functions only see their parameters"),
+        None => Diagnostic::error(format!("unknown state field `{}`", field), span)
+            .with_primary("not declared in this agent's `state` block"),
+    }
+}
+
+/// Whether every path through a block ends in `return`
+fn always_returns(body: &[Stmt]) -> bool {
+    body.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Return(_) => true,
+        StmtKind::If {
+            then_body,
+            else_body,
+            ..
+        } => always_returns(then_body) && always_returns(else_body),
+        // Matches are checked for exhaustiveness separately
+        StmtKind::Match { arms, .. } => !arms.is_empty() && arms.iter().all(|arm| always_returns(&arm.body)),
+        _ => false,
+    })
+}
+
+/// Whether an expression names storage a mutating method can write back to
+fn is_place(env: &Env, expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Var(name) => env.locals.contains_key(name),
+        ExprKind::StateField(_) => true,
+        _ => false,
+    }
+}
+
+/// Combine two types that must agree, filling `Infer` holes from either side
+fn unify(a: &Type, b: &Type) -> Option<Type> {
+    match (a, b) {
+        // An error type has already been reported; don't cascade
+        (Type::Error, _) | (_, Type::Error) => Some(Type::Error),
+        (Type::Infer, other) | (other, Type::Infer) => Some(other.clone()),
+        (Type::List(a), Type::List(b)) => Some(Type::List(Box::new(unify(a, b)?))),
+        (Type::Option(a), Type::Option(b)) => Some(Type::Option(Box::new(unify(a, b)?))),
+        (Type::Map(ak, av), Type::Map(bk, bv)) => {
+            Some(Type::Map(Box::new(unify(ak, bk)?), Box::new(unify(av, bv)?)))
+        }
+        (Type::Named(a, aargs), Type::Named(b, bargs)) if a == b => Some(Type::Named(a.clone(), unify_args(aargs, bargs)?)),
+        (Type::Ref(a, aargs), Type::Ref(b, bargs)) if a == b => Some(Type::Ref(a.clone(), unify_args(aargs, bargs)?)),
+        (a, b) if a == b => Some(a.clone()),
+        _ => None,
+    }
+}
+
+/// An example of a full type, with `Int` wherever a hole remains
+fn fill_holes(ty: &Type) -> Type {
+    let all = |args: &[Type]| args.iter().map(fill_holes).collect();
+    match ty {
+        Type::Infer => Type::Int,
+        Type::Named(name, args) => Type::Named(name.clone(), all(args)),
+        Type::Ref(name, args) => Type::Ref(name.clone(), all(args)),
+        Type::List(elem) => Type::List(Box::new(fill_holes(elem))),
+        Type::Option(elem) => Type::Option(Box::new(fill_holes(elem))),
+        Type::Map(key, value) => Type::Map(Box::new(fill_holes(key)), Box::new(fill_holes(value))),
+        other => other.clone(),
+    }
+}
+
+fn unify_args(a: &[Type], b: &[Type]) -> Option<Vec<Type>> {
+    if a.len() != b.len() {
+        return None;
+    }
+    a.iter().zip(b).map(|(a, b)| unify(a, b)).collect()
+}
+
+/// Parameter types and result of a built-in method; a `None` result means
+/// the method updates its receiver in place
+fn method_signature(receiver: &Type, method: &str) -> Option<(Vec<Type>, Option<Type>)> {
+    let signature = match (receiver, method) {
+        (Type::List(_) | Type::Map(..) | Type::String, "len") => (vec![], Some(Type::Int)),
+        (Type::String, "contains") => (vec![Type::String], Some(Type::Bool)),
+        (Type::String, "split") => (vec![Type::String], Some(Type::List(Box::new(Type::String)))),
+        (Type::String, "to_upper" | "to_lower" | "trim") => (vec![], Some(Type::String)),
+        (Type::String, "parse_int") => (vec![], Some(Type::Option(Box::new(Type::Int)))),
+        (Type::List(elem), "push") => (vec![(**elem).clone()], None),
+        (Type::List(elem), "get") => (vec![Type::Int], Some(Type::Option(elem.clone()))),
+        (Type::List(elem), "contains") => (vec![(**elem).clone()], Some(Type::Bool)),
+        (Type::Map(key, value), "get") => (vec![(**key).clone()], Some(Type::Option(value.clone()))),
+        (Type::Map(key, value), "insert") => (vec![(**key).clone(), (**value).clone()], None),
+        (Type::Map(key, _), "contains") => (vec![(**key).clone()], Some(Type::Bool)),
+        (Type::Option(_), "is_some") => (vec![], Some(Type::Bool)),
+        (Type::Option(elem), "unwrap_or") => (vec![(**elem).clone()], Some((**elem).clone())),
+        // Whether the timer was still pending
+        (Type::Timer, "cancel") => (vec![], Some(Type::Bool)),
+        _ => return None,
+    };
+    Some(signature)
+}
+
+fn infer_binop(op: &BinOp, left: &Type, right: &Type) -> Option<Type> {
+    let numeric = |ty: &Type| matches!(ty, Type::Int | Type::Float);
+    match (op, left, right) {
+        (BinOp::Add, Type::String, Type::String) => Some(Type::String),
+        (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod, Type::Int, Type::Int) => {
+            Some(Type::Int)
+        }
+        // Mixing Int and Float promotes to Float
+        (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod, l, r) if numeric(l) && numeric(r) => {
+            Some(Type::Float)
+        }
+        (BinOp::Add | BinOp::Sub, Type::Duration, Type::Duration) => Some(Type::Duration),
+        (BinOp::Mul, Type::Duration, n) | (BinOp::Mul, n, Type::Duration) if numeric(n) => Some(Type::Duration),
+        (BinOp::Div, Type::Duration, n) if numeric(n) => Some(Type::Duration),
+        (BinOp::Div, Type::Duration, Type::Duration) => Some(Type::Float),
+        (BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge, l, r) if numeric(l) && numeric(r) => Some(Type::Bool),
+        (BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge, Type::Duration, Type::Duration) => Some(Type::Bool),
+        (BinOp::Eq | BinOp::Ne, l, r) if numeric(l) && numeric(r) => Some(Type::Bool),
+        (BinOp::Eq | BinOp::Ne, l, r) if unify(l, r).is_some() => Some(Type::Bool),
+        (BinOp::And | BinOp::Or, Type::Bool, Type::Bool) => Some(Type::Bool),
+        _ => None,
+    }
+}
+
+fn infer_unary(op: &UnaryOp, operand: &Type) -> Option<Type> {
+    match (op, operand) {
+        (UnaryOp::Neg, Type::Int) => Some(Type::Int),
+        (UnaryOp::Neg, Type::Float) => Some(Type::Float),
+        (UnaryOp::Not, Type::Bool) => Some(Type::Bool),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::ProgramParser;
+
+    fn check(source: &str) -> std::result::Result<(), Vec<Diagnostic>> {
+        let program = ProgramParser::new().parse(0, source).expect("parses");
+        typecheck(&program)
+    }
+
+    const TYPES: &str = r#"
+        type TicketMsg { NewTicket { id: Int, priority: String, replyTo: Ref[Response] } }
+        type Response { Ack { ticket_id: Int } }
+    "#;
+
+    /// `TYPES` and an agent `A` with the given state, running `body` on `NewTicket { params }`
+    fn handler(state: &str, params: &str, body: &str) -> String {
+        format!(
+            "{} agent A {{ state {{ {} }} on NewTicket {{ {} }} -> {{ {} }} }}",
+            TYPES, state, params, body
+        )
+    }
+
+    /// Assert the first diagnostic for `source` reads `message` and points at `spanned`
+    #[track_caller]
+    fn assert_error(source: &str, message: &str, spanned: &str) {
+        let diagnostics = check(source).expect_err("should not typecheck");
+        let span = diagnostics[0].primary.span;
+        assert_eq!((diagnostics[0].message.as_str(), &source[span.start..span.end]), (message, spanned));
+    }
+
+    #[test]
+    fn test_send_checks_fields() {
+        let ok = handler("", "id, replyTo", "send replyTo Ack { ticket_id: id };");
+        assert!(check(&ok).is_ok());
+
+        let wrong_type = ok.replace("ticket_id: id", "ticket_id: \"one\"");
+        assert_error(&wrong_type, "mismatched types", "\"one\"");
+
+        let missing = ok.replace("ticket_id: id", "");
+        assert_error(&missing, "missing field `ticket_id` in `Ack`", "send replyTo Ack {  };");
+
+        let unknown_variant = ok.replace("send replyTo Ack", "send replyTo Nack");
+        let message = "unknown variant `Nack` for Ref[Response]";
+        assert_error(&unknown_variant, message, "send replyTo Nack { ticket_id: id };");
+    }
+
+    #[test]
+    fn test_binop_types() {
+        let program = |body: &str| handler("s: String = \"\"; b: Bool = false;", "id, priority", body);
+        assert!(check(&program("state.s = priority + \"!\";")).is_ok());
+        assert_error(&program("state.s = id + 1;"), "mismatched types", "id + 1");
+        assert_error(&program("state.b = id + 1;"), "mismatched types", "id + 1");
+        assert_error(
+            &program("state.s = priority + 1;"),
+            "operator `+` cannot be applied to String and Int",
+            "priority + 1",
+        );
+    }
+
+    #[test]
+    fn test_reports_every_error() {
+        let body = "state.n = x; send replyTo Ack { ticket_id: y }; state.n = state.n + true;";
+        let source = handler("n: Int = \"zero\";", "id, replyTo", body);
+        let diagnostics = check(&source).unwrap_err();
+        let errors: Vec<(&str, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), &source[d.primary.span.start..d.primary.span.end]))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("mismatched types", "\"zero\""),
+                ("undefined variable `x`", "x"),
+                ("undefined variable `y`", "y"),
+                ("operator `+` cannot be applied to Int and Bool", "state.n + true"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_declared_protocol_is_exhaustive() {
+        let types = "type TicketMsg { NewTicket { id: Int }, Status { } } type Other { Ping { } }";
+        let agent = |handlers: &str| format!("{} agent A : TicketMsg {{ state {{ }} {} }}", types, handlers);
+
+        assert!(check(&agent("on NewTicket { id } -> { } on Status { } -> { }")).is_ok());
+
+        let missing = agent("on NewTicket { id } -> { }");
+        assert_error(&missing, "agent `A` has no handler for `Status`", "agent A : TicketMsg");
+
+        let foreign = agent("on NewTicket { } -> { } on Status { } -> { } on Ping { } -> { }");
+        assert_error(&foreign, "`Ping` is not a variant of `TicketMsg`", "on Ping { }");
+
+        let duplicate = agent("on NewTicket { } -> { } on Status { } -> { } on Status { } -> { }");
+        assert_error(&duplicate, "duplicate handler for `Status` in agent `A`", "on Status { }");
+
+        let unknown = agent("on NewTicket { } -> { }").replace(": TicketMsg", ": Tickets");
+        assert_error(&unknown, "unknown type `Tickets`", "agent A : Tickets");
+    }
+
+    #[test]
+    fn test_inferred_protocol_is_unambiguous() {
+        let types = "type Health { Ping { } } type Gossip { Ping { }, Rumor { } }";
+        let agent = |protocol: &str| format!("{} agent A{} {{ state {{ }} on Ping {{ }} -> {{ }} }}", types, protocol);
+
+        assert!(check(&agent(" : Gossip")).is_err());
+        assert!(check(&agent(" : Health")).is_ok());
+
+        let source = agent("");
+        assert_error(&source, "cannot infer the protocol of `A`", "on Ping { }");
+        let diagnostics = check(&source).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        let ambiguous = &diagnostics[0];
+        let labels: Vec<&str> = ambiguous.secondary.iter().map(|l| l.message.as_str()).collect();
+        assert_eq!(labels, ["declared by `Health`", "declared by `Gossip`"]);
+        assert_eq!(ambiguous.notes, ["declare it with `agent A : Health`"]);
+    }
+
+    #[test]
+    fn test_state_is_namespaced() {
+        let program = |body: &str| handler("count: Int = 0;", "id", body);
+        assert!(check(&program("state.count = state.count + id;")).is_ok());
+        assert_error(&program("count = id;"), "undefined variable `count`", "count = id;");
+        assert_error(&program("state.id = 1;"), "unknown state field `id`", "state.id = 1;");
+        assert_error(&program("let n = state.total;"), "unknown state field `total`", "state.total");
+    }
+
+    #[test]
+    fn test_comparison_and_logic() {
+        let program = |body: &str| handler("b: Bool = false;", "id, priority", body);
+        assert!(check(&program("state.b = id >= 2 && !(priority == \"low\") || id % 2 != 0;")).is_ok());
+        assert_error(
+            &program("state.b = id && true;"),
+            "operator `&&` cannot be applied to Int and Bool",
+            "id && true",
+        );
+        assert_error(
+            &program("state.b = -priority == \"x\";"),
+            "operator `-` cannot be applied to String",
+            "-priority",
+        );
+    }
+
+    #[test]
+    fn test_if_and_match() {
+        let types = "type Priority { High { }, Low { reason: String } } \
+                     type Msg { Route { priority: Priority, urgent: Bool } }";
+        let program = |body: &str| {
+            format!(
+                "{} agent A : Msg {{ state {{ n: Int = 0; }} on Route {{ priority, urgent }} -> {{ {} }} }}",
+                types, body
+            )
+        };
+        let ok = "if urgent { state.n = 1; } else if state.n > 2 { state.n = 2; } else { } \
+                  match priority { High { } => { state.n = 3; } Low { reason } => { log(reason); } }";
+        assert!(check(&program(ok)).is_ok());
+        assert!(check(&program("match Priority::Low { reason: \"late\" } { _ => { } }")).is_ok());
+
+        assert_error(&program("if state.n { }"), "mismatched types", "state.n");
+
+        let missing = program("match priority { High { } => { } }");
+        assert_error(&missing, "non-exhaustive match: `Low` not covered", "priority");
+
+        let scoped = program("match priority { Low { reason } => { } _ => { } } log(reason);");
+        assert_error(&scoped, "undefined variable `reason`", "reason");
+    }
+
+    #[test]
+    fn test_let_is_block_scoped() {
+        let program = |body: &str| handler("n: Int = 0;", "id", body);
+        assert!(check(&program("let x: Int = id + 1; let y = x * 2; y = y + 1; state.n = y;")).is_ok());
+        assert!(check(&program("let x = \"a\"; if true { let x = 2; state.n = x; } log(x);")).is_ok());
+        assert_error(&program("let s: String = id;"), "mismatched types", "id");
+        assert_error(&program("if id > 1 { let x = 1; } state.n = x;"), "undefined variable `x`", "x");
+    }
+
+    #[test]
+    fn test_loops() {
+        let program = |body: &str| handler("n: Int = 0;", "id", body);
+        let ok = "for i in 0..id { if i % 2 == 0 { continue; } state.n = state.n + i; } \
+                  while state.n > 10 { state.n = state.n - 1; if state.n == 12 { break; } }";
+        assert!(check(&program(ok)).is_ok());
+        assert_error(&program("while id { }"), "mismatched types", "id");
+        assert_error(&program("for i in 0..\"x\" { }"), "mismatched types", "\"x\"");
+        assert_error(&program("if true { break; }"), "`break` outside of a loop", "break;");
+    }
+
+    #[test]
+    fn test_collections() {
+        let program = |body: &str| {
+            handler("queue: List[Int] = []; seen: Map[String, Int] = {};", "id, priority", body)
+        };
+        let ok = "state.queue.push(id); let n = state.seen.get(priority).unwrap_or(0); \
+                  state.seen.insert(priority, n + state.queue.len()); \
+                  match state.queue.get(0) { some(first) => { let hit: Bool = state.seen[\"first\"] == first; } none => { } } \
+                  for queued in state.queue { let copy: List[Int] = [queued]; }";
+        assert!(check(&program(ok)).is_ok());
+        assert_error(&program("state.queue.push(priority);"), "mismatched types", "priority");
+        assert_error(&program("let n: Int = state.seen[id];"), "mismatched types", "id");
+        assert_error(&program("let empty = [];"), "type annotations needed", "[]");
+
+        let option = program("match state.queue.get(0) { some(x) => { } }");
+        assert_error(&option, "non-exhaustive match: `none` not covered", "state.queue.get(0)");
+    }
+
+    #[test]
+    fn test_float_and_duration() {
+        let state = "score: Float = -1.5; sla: Duration = 30s; n: Int = -2;";
+        let program = |body: &str| handler(state, "id", body);
+        let ok = "state.score = state.score * id + 0.5; state.sla = state.sla * 2 - 500ms; \
+                  let ratio: Float = state.sla / 1m; if id < 2.5 && state.sla >= 1h { state.n = -id; }";
+        assert!(check(&program(ok)).is_ok());
+        assert_error(&program("state.n = id * 1.0;"), "mismatched types", "id * 1.0");
+        assert_error(
+            &program("state.sla = state.sla + 1;"),
+            "operator `+` cannot be applied to Duration and Int",
+            "state.sla + 1",
+        );
+        assert_error(&program("state.score = 2;"), "mismatched types", "2");
+
+        // Literals out of range are rejected as the program is parsed
+        let literals = [
+            ("99999999999999999h", "duration `99999999999999999h` is too large"),
+            ("99999999999999999999", "integer literal `99999999999999999999` is too large"),
+        ];
+        for (literal, message) in literals {
+            let source = program(&format!("log({});", literal));
+            let Err(lalrpop_util::ParseError::User { error }) = ProgramParser::new().parse(0, &source) else {
+                panic!("`{}` should be rejected", literal);
+            };
+            let span = error.primary.span;
+            assert_eq!((error.message.as_str(), &source[span.start..span.end]), (message, literal));
+        }
+    }
+
+    #[test]
+    fn test_string_builtins() {
+        let program = |body: &str| handler("n: Int = 0;", "id, priority", body);
+        let ok = "let parts: List[String] = priority.to_upper().split(\",\"); \
+                  state.n = priority.len() + \"7\".parse_int().unwrap_or(0); \
+                  if priority.contains(\"hi\") { log(\"ticket ${id} has ${parts.len()} parts\"); }";
+        assert!(check(&program(ok)).is_ok());
+        assert_error(&program("state.n = priority.parse_int();"), "mismatched types", "priority.parse_int()");
+        assert_error(&program("log(\"${[id]}\");"), "cannot interpolate a value of type List[Int]", "[id]");
+        assert_error(&program("log(\"${missing}\");"), "undefined variable `missing`", "missing");
+    }
+
+    #[test]
+    fn test_functions() {
+        let program = |functions: &str, body: &str| {
+            format!("{} {}", functions, handler("n: Int = 0;", "id, priority", body))
+        };
+        let score = "fn score(p: String, boost: Int) -> Int { if p == \"high\" { return 10 + boost; } return boost; }";
+        let note = "fn note(id: Int) uses log { log(id); }";
+        let ok = program(&format!("{} {}", score, note), "state.n = score(priority, id); note(id);");
+        assert!(check(&ok).is_ok());
+
+        assert_error(&program(score, "state.n = score(id, 1);"), "mismatched types", "id");
+        let arity = program(score, "state.n = score(priority);");
+        assert_error(&arity, "`score` takes 2 argument(s) but 1 were given", "score(priority)");
+
+        let effect = program("fn f() { log(1); }", "f();");
+        assert_error(&effect, "function `f` performs effect `log` without declaring it", "log(1);");
+
+        let transitive = program(&format!("{} fn f() {{ note(1); }}", note), "");
+        assert_error(&transitive, "`note` uses effect `log`, which `f` does not declare", "note(1);");
+
+        let missing = program("fn f(x: Int) -> Int { if x > 0 { return x; } }", "");
+        assert_error(&missing, "function `f` may finish without returning a value", "fn f(x: Int) -> Int");
+    }
+
+    #[test]
+    fn test_generics() {
+        let program = |body: &str| {
+            format!(
+                r#"
+                type Reply[T] {{ Ok {{ value: T }}, Err {{ reason: String }} }}
+                type CacheMsg[K, V] {{ Put {{ key: K, value: V }}, Get {{ key: K, replyTo: Ref[Reply[V]] }} }}
+                agent Cache[K, V] : CacheMsg[K, V] {{
+                    state {{ entries: Map[K, V] = {{}}; }}
+                    on Put {{ key, value }} -> {{ state.entries.insert(key, value); }}
+                    on Get {{ key, replyTo }} -> {{
+                        match state.entries.get(key) {{
+                            some(value) => {{ send replyTo Ok {{ value: value }}; }}
+                            none => {{ send replyTo Err {{ reason: "missing" }}; }}
+                        }}
+                    }}
+                }}
+                agent Client : Reply[String] {{
+                    state {{ }}
+                    on Ok {{ value }} -> {{ {} }}
+                    on Err {{ reason }} -> {{ }}
+                }}
+                "#,
+                body
+            )
+        };
+        assert!(check(&program("send Cache Put { key: 1, value: value };")).is_ok());
+        assert!(check(&program("let r: Reply[Int] = Reply::Ok { value: 1 };")).is_ok());
+
+        let wrong_arg = program("let r: Reply[Int] = Reply::Ok { value: value };");
+        assert_error(&wrong_arg, "mismatched types", "Reply::Ok { value: value }");
+
+        let ambiguous = program("let r = Reply::Err { reason: value };");
+        assert_error(&ambiguous, "type annotations needed", "Reply::Err { reason: value }");
+
+        let arity = program("let r: Reply = Reply::Ok { value: 1 };");
+        let message = "`Reply` takes 1 type argument(s) but 0 were given";
+        assert_error(&arity, message, "let r: Reply = Reply::Ok { value: 1 };");
+
+        // Inside a generic agent its parameters are opaque
+        let opaque = program("").replace("insert(key, value)", "insert(key, 1)");
+        assert_error(&opaque, "mismatched types", "1");
+    }
+
+    #[test]
+    fn test_self_and_spawn() {
+        let program = |body: &str| {
+            format!(
+                r#"
+                type Reply[T] {{ Ok {{ value: T }} }}
+                type DeskMsg {{ Start {{ }}, Done {{ by: Ref[WorkerMsg] }} }}
+                type WorkerMsg {{ Work {{ boss: Ref[DeskMsg] }} }}
+                agent Desk : DeskMsg {{
+                    state {{ }}
+                    on Start {{ }} -> {{ {} }}
+                    on Done {{ by }} -> {{ }}
+                }}
+                agent Worker : WorkerMsg {{
+                    state {{ }}
+                    on Work {{ boss }} -> {{ send boss Done {{ by: self }}; }}
+                }}
+                agent Echo[T] : Reply[T] {{
+                    state {{ }}
+                    on Ok {{ value }} -> {{ }}
+                }}
+                "#,
+                body
+            )
+        };
+        assert!(check(&program("let w = spawn Worker(); send w Work { boss: self };")).is_ok());
+        assert!(check(&program("let e = spawn Echo[Int](); send e Ok { value: 1 };")).is_ok());
+
+        let wrong_ref = program("let w = spawn Worker(); send w Work { boss: w };");
+        assert_error(&wrong_ref, "mismatched types", "w");
+
+        let wrong_arg = program("let e = spawn Echo[Int](); send e Ok { value: true };");
+        assert_error(&wrong_arg, "mismatched types", "true");
+
+        assert_error(&program("let w = spawn Nobody();"), "unknown agent `Nobody`", "spawn Nobody()");
+
+        let outside = format!("{} fn f() {{ let me = self; }}", program(""));
+        assert_error(&outside, "`self` outside of a handler", "self");
+    }
+
+    #[test]
+    fn test_constructor_params() {
+        let program = |body: &str| {
+            format!(
+                r#"
+                type Report {{ Start {{ }}, Done {{ name: String }} }}
+                type Job {{ Work {{ }} }}
+                agent Boss : Report {{
+                    state {{ }}
+                    on Start {{ }} -> {{ {} }}
+                    on Done {{ name }} -> {{ }}
+                }}
+                agent Worker(id: Int, boss: Ref[Report]) : Job {{
+                    state {{ name: String = "w${{id}}"; label: String = state.name + "!"; }}
+                    on Work {{ }} -> {{ send boss Done {{ name: state.label }}; }}
+                }}
+                "#,
+                body
+            )
+        };
+        assert!(check(&program("let w = spawn Worker(1, self); send w Work { };")).is_ok());
+
+        assert_error(&program("let w = spawn Worker(self, 1);"), "mismatched types", "self");
+
+        let arity = program("let w = spawn Worker(1);");
+        assert_error(&arity, "`Worker` takes 2 argument(s) but 1 were given", "spawn Worker(1)");
+
+        let by_name = program("send Worker Work { };");
+        assert_error(&by_name, "agent `Worker` has no instance to refer to by name", "Worker");
+
+        let assign = program("").replace("send boss Done", "id = 2; send boss Done");
+        assert_error(&assign, "cannot assign to constructor parameter `id`", "id = 2;");
+    }
+
+    #[test]
+    fn test_ask() {
+        let program = |body: &str| {
+            format!(
+                r#"
+                type Query {{ Status {{ verbose: Bool, replyTo: Ref[StatusReply] }}, Ping {{ }} }}
+                type StatusReply {{ Up {{ load: Int }} }}
+                type Control {{ Start {{ }} }}
+                agent Server : Query {{
+                    state {{ }}
+                    on Status {{ verbose, replyTo }} -> {{ send replyTo Up {{ load: 1 }}; }}
+                    on Ping {{ }} -> {{ }}
+                }}
+                agent Client : Control {{
+                    state {{ }}
+                    on Start {{ }} -> {{ {} }}
+                }}
+                "#,
+                body
+            )
+        };
+        let ok = "let r: Option[StatusReply] = ask Server Status { verbose: true } within 2s;";
+        assert!(check(&program(ok)).is_ok());
+
+        let given = program("let r = ask Server Status { verbose: true, replyTo: Server } within 2s;");
+        assert_error(&given, "`replyTo` is filled in by `ask`", "Server");
+        let no_reply = program("let r = ask Server Ping {} within 2s;");
+        assert_error(&no_reply, "`Ping` cannot be asked for a reply", "ask Server Ping {} within 2s");
+        let itself = program("let r = ask self Start { } within 2s;");
+        assert_error(&itself, "an agent cannot `ask` itself", "self");
+        let timeout = program("let r = ask Server Status { verbose: true } within 2;");
+        assert_error(&timeout, "mismatched types", "2");
+        // The reply is optional, as the time may run out
+        let not_option = program("let r: StatusReply = ask Server Status { verbose: true } within 2s;");
+        assert_error(&not_option, "mismatched types", "ask Server Status { verbose: true } within 2s");
+    }
+
+    #[test]
+    fn test_schedule() {
+        let program = |body: &str| {
+            format!(
+                r#"
+                type Msg {{ Start {{ }}, Tick {{ n: Int }} }}
+                agent Clock : Msg {{
+                    state {{ timer: Option[Timer] = none; }}
+                    on Start {{ }} -> {{ {} }}
+                    on Tick {{ n }} -> {{ }}
+                }}
+                "#,
+                body
+            )
+        };
+        let ok = "schedule 5s send self Tick { n: 1 }; state.timer = some(every 1s * 2 send Clock Tick { n: 2 });";
+        assert!(check(&program(ok)).is_ok());
+        let cancel = "let t: Timer = schedule 5s send self Tick { n: 1 }; let stopped: Bool = t.cancel();";
+        assert!(check(&program(cancel)).is_ok());
+
+        assert_error(&program("every 5 send self Tick { n: 1 };"), "mismatched types", "5");
+        assert_error(&program("schedule 5s send self Tick { };"), "missing field `n` in `Tick`", "schedule 5s send self Tick { }");
+        assert_error(&program("schedule 5s send self Tock { };"), "unknown variant `Tock` for Ref[Msg]", "schedule 5s send self Tock { }");
+    }
+
+    #[test]
+    fn test_supervisors() {
+        let program = |supervisors: &str| {
+            format!(
+                r#"
+                type M {{ Ping {{ }} }}
+                agent A : M {{ state {{ }} on Ping {{ }} -> {{ }} }}
+                agent B : M {{ state {{ }} on Ping {{ }} -> {{ }} }}
+                agent W(n: Int) : M {{ state {{ }} on Ping {{ }} -> {{ }} }}
+                {}
+                "#,
+                supervisors
+            )
+        };
+        let tree = "supervisor Root { strategy: rest_for_one, max_restarts: 3 within 10s, children { A, Inner } }
+                    supervisor Inner { strategy: one_for_all, children { B } }";
+        assert!(check(&program(tree)).is_ok());
+
+        let params = program("supervisor S { strategy: one_for_one, children { W } }");
+        assert_error(&params, "cannot supervise `W`", "W");
+        let unknown = program("supervisor S { strategy: one_for_one, children { C } }");
+        assert_error(&unknown, "unknown agent or supervisor `C`", "C");
+
+        let twice = program(
+            "supervisor S { strategy: one_for_one, children { A } }
+             supervisor T { strategy: one_for_one, children { A } }",
+        );
+        assert_error(&twice, "`A` is supervised twice", "A");
+        // At the second mention
+        assert_eq!(check(&twice).unwrap_err()[0].primary.span.start, twice.rfind('A').unwrap());
+
+        let cycle = program(
+            "supervisor S { strategy: one_for_one, children { T } }
+             supervisor T { strategy: one_for_one, children { S } }",
+        );
+        assert_eq!(check(&cycle).unwrap_err().len(), 1);
+        assert_error(&cycle, "supervisor `S` supervises itself", "supervisor S");
+
+        // Strategies are checked as the program is parsed
+        let source = program("supervisor S { strategy: one_for_some, children { A } }");
+        let Err(lalrpop_util::ParseError::User { error }) = ProgramParser::new().parse(0, &source) else {
+            panic!("expected an unknown strategy");
+        };
+        let span = error.primary.span;
+        assert_eq!(
+            (error.message.as_str(), &source[span.start..span.end]),
+            ("unknown restart strategy `one_for_some`", "one_for_some")
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_hooks() {
+        let program = |hooks: &str| {
+            format!(
+                r#"
+                type Msg {{ Ping {{ }} }}
+                agent Pinger(first: Int) : Msg {{
+                    state {{ count: Int = first; }}
+                    on Ping {{ }} -> {{ state.count = state.count + 1; }}
+                    {}
+                }}
+                "#,
+                hooks
+            )
+        };
+        let ok = r#"on start -> { send self Ping { }; state.count = first; }
+                    on stop -> { log("pinged", state.count); }
+                    on restart -> { state.count = 0; }"#;
+        assert!(check(&program(ok)).is_ok());
+
+        assert_error(&program("on stop -> { state.count = \"done\"; }"), "mismatched types", "\"done\"");
+        let twice = program("on start -> { } on start -> { }");
+        assert_error(&twice, "duplicate `on start` in agent `Pinger`", "on start");
+        // At the second hook
+        assert_eq!(check(&twice).unwrap_err()[0].primary.span.start, twice.rfind("on start").unwrap());
+
+        // Events are checked as the program is parsed
+        let source = program("on pause -> { }");
+        let Err(lalrpop_util::ParseError::User { error }) = ProgramParser::new().parse(0, &source) else {
+            panic!("expected an unknown event");
+        };
+        let span = error.primary.span;
+        assert_eq!((error.message.as_str(), &source[span.start..span.end]), ("unknown lifecycle event `pause`", "on pause"));
     }
 }
@@ -1,5 +1,5 @@
 // Simple working example
-use agentr::{spawn_actor, Effect, EffectContext, Message};
+use agentr::{ActorSystem, Effect, EffectContext, Message};
 use anyhow::Result;
 use std::sync::Arc;
 
@@ -17,8 +17,10 @@ async fn main() -> Result<()> {
     let effect_ctx = Arc::new(EffectContext::new());
     let log_cap = effect_ctx.grant(Effect::Log).await;
 
+    let system = ActorSystem::new();
+
     // Spawn ticket handler agent
-    let (ticket_ref, _handle) = spawn_actor(10, move |msg: TicketMsg| {
+    let ticket_ref = system.spawn(10, move |msg: TicketMsg| {
         let effect_ctx = effect_ctx.clone();
         let log_cap = log_cap.clone();
 
@@ -57,8 +59,8 @@ async fn main() -> Result<()> {
         })
         .await?;
 
-    // Give actors time to process
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    // Let actors process their queued messages, then stop them
+    system.shutdown().await?;
 
     println!("\n✓ Example completed successfully");
 
@@ -1,7 +1,14 @@
 // Actor system implementation
 use crate::mailbox::Message;
 use anyhow::Result;
-use tokio::sync::mpsc;
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
 
 /// Typed reference to an actor
 #[derive(Clone)]
@@ -25,22 +32,251 @@ impl<T: Message> ActorRef<T> {
         self.tx.try_send(msg)?;
         Ok(())
     }
+
+    /// Send the message built by `make` around a fresh reply channel, and wait
+    /// up to `timeout` for the answer. Yields `None` if the time runs out or
+    /// the actor drops the channel without replying.
+    pub async fn ask<R, F>(&self, make: F, timeout: Duration) -> Result<Option<R>>
+    where
+        R: Send + 'static,
+        F: FnOnce(ReplyTo<R>) -> T,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.send(make(ReplyTo::new(tx))).await?;
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(reply)) => Ok(Some(reply)),
+            Ok(Err(_)) | Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Where the answer to an `ask` goes; carried inside the request message.
+/// Clones share the channel, and only the first reply is delivered.
+pub struct ReplyTo<R> {
+    tx: Arc<Mutex<Option<oneshot::Sender<R>>>>,
+}
+
+impl<R> ReplyTo<R> {
+    fn new(tx: oneshot::Sender<R>) -> Self {
+        Self {
+            tx: Arc::new(Mutex::new(Some(tx))),
+        }
+    }
+
+    /// Answer the request; fails if it was already answered or the asker
+    /// stopped waiting
+    pub fn send(&self, reply: R) -> Result<()> {
+        let tx = self
+            .tx
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("request was already answered"))?;
+        tx.send(reply).map_err(|_| anyhow::anyhow!("asker is no longer waiting"))
+    }
+}
+
+impl<R> Clone for ReplyTo<R> {
+    fn clone(&self) -> Self {
+        Self { tx: self.tx.clone() }
+    }
+}
+
+impl<R> fmt::Debug for ReplyTo<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ReplyTo")
+    }
+}
+
+pub(crate) type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+type Hook = Box<dyn FnOnce() -> BoxFuture<Result<()>> + Send>;
+
+/// Callbacks around an actor's message loop
+#[derive(Default)]
+pub struct Lifecycle {
+    on_start: Option<Hook>,
+    on_stop: Option<Hook>,
+    on_restart: Option<Hook>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run before the first message is handled; an error stops the actor
+    /// without handling any
+    pub fn on_start<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.on_start = Some(Box::new(move || Box::pin(hook())));
+        self
+    }
+
+    /// Run once the actor stops handling messages: when its mailbox is
+    /// closed and drained or, for a supervised actor, a handler failed or
+    /// its supervisor stopped it
+    pub fn on_stop<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.on_stop = Some(Box::new(move || Box::pin(hook())));
+        self
+    }
+
+    /// Run when a supervisor restarts the actor, before the start hook; an
+    /// error counts as another crash
+    pub fn on_restart<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.on_restart = Some(Box::new(move || Box::pin(hook())));
+        self
+    }
+
+    /// Run the restart hook, if any
+    pub(crate) async fn restarted(&mut self) -> Result<()> {
+        match self.on_restart.take() {
+            Some(hook) => hook().await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run an actor to completion: its start hook, every message until the
+/// mailbox closes, then its stop hook. Once `stop` completes, with `drain`
+/// the mailbox is closed so only messages already queued are still handled;
+/// without, the actor stops after the message in hand and leaves the rest
+/// queued. Handler errors, panics included, are logged and skipped unless
+/// `fail_fast`, where the first one stops the actor.
+pub(crate) async fn run_actor<T, F, Fut>(
+    rx: &mut mpsc::Receiver<T>,
+    lifecycle: Lifecycle,
+    handler: F,
+    fail_fast: bool,
+    stop: impl Future<Output = ()>,
+    drain: bool,
+) -> Result<()>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    if let Some(hook) = lifecycle.on_start {
+        hook().await?;
+    }
+
+    let mut result = Ok(());
+    let mut stop = pin!(stop);
+    let mut stopping = false;
+    loop {
+        let msg = tokio::select! {
+            msg = rx.recv() => msg,
+            () = &mut stop, if !stopping => {
+                if !drain {
+                    break;
+                }
+                rx.close();
+                stopping = true;
+                continue;
+            }
+        };
+        let Some(msg) = msg else { break };
+        if let Err(e) = catch_panic(handler(msg)).await {
+            if fail_fast {
+                result = Err(e);
+                break;
+            }
+            eprintln!("Actor handler error: {}", e);
+        }
+    }
+
+    if let Some(hook) = lifecycle.on_stop {
+        // A handler error takes precedence over one from the hook
+        result = result.and(hook().await);
+    }
+    result
 }
 
-/// Handle to a running actor
+/// Turn a panic while polling `future` into an error, so the actor outlives it
+async fn catch_panic(future: impl Future<Output = Result<()>>) -> Result<()> {
+    let mut future = pin!(future);
+    std::future::poll_fn(|cx| match std::panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+        Ok(poll) => poll,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            Poll::Ready(Err(anyhow::anyhow!("handler panicked: {}", message)))
+        }
+    })
+    .await
+}
+
+/// Handle to a running actor. Dropping it leaves the actor running.
 pub struct ActorHandle {
     handle: tokio::task::JoinHandle<()>,
+    stop: oneshot::Sender<()>,
 }
 
 impl ActorHandle {
+    /// Wait for the actor to exit, which it does once every `ActorRef` is
+    /// dropped or it is stopped
     pub async fn join(self) -> Result<()> {
         self.handle.await?;
         Ok(())
     }
+
+    /// Stop accepting messages, handle those already queued, run the stop
+    /// hook and wait for the actor to exit
+    pub async fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        self.handle.await?;
+        Ok(())
+    }
+
+    /// Like `stop`, but kill the actor if it has not exited within `timeout`
+    pub async fn stop_timeout(self, timeout: Duration) -> Result<()> {
+        let _ = self.stop.send(());
+        let task = self.handle.abort_handle();
+        match tokio::time::timeout(timeout, self.handle).await {
+            Ok(joined) => {
+                joined?;
+                Ok(())
+            }
+            Err(_) => {
+                task.abort();
+                anyhow::bail!("Actor did not stop within {:?} and was killed", timeout)
+            }
+        }
+    }
+
+    /// Abort the actor at its next await point, dropping queued messages
+    /// without running the stop hook
+    pub async fn kill(self) {
+        self.handle.abort();
+        let _ = self.handle.await;
+    }
 }
 
 /// Spawn an actor with a message handler
 pub fn spawn_actor<T, F, Fut>(mailbox_size: usize, handler: F) -> (ActorRef<T>, ActorHandle)
+where
+    T: Message,
+    F: Fn(T) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Result<()>> + Send,
+{
+    spawn_actor_with(mailbox_size, Lifecycle::default(), handler)
+}
+
+/// Spawn an actor with a message handler and lifecycle hooks
+pub fn spawn_actor_with<T, F, Fut>(mailbox_size: usize, lifecycle: Lifecycle, handler: F) -> (ActorRef<T>, ActorHandle)
 where
     T: Message,
     F: Fn(T) -> Fut + Send + 'static,
@@ -48,16 +284,21 @@ where
 {
     let (tx, mut rx) = mpsc::channel::<T>(mailbox_size);
     let actor_ref = ActorRef::new(tx);
+    let (stop, stopped) = oneshot::channel();
 
     let handle = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if let Err(e) = handler(msg).await {
-                eprintln!("Actor handler error: {}", e);
+        // A dropped handle never stops the actor
+        let stop = async {
+            if stopped.await.is_err() {
+                std::future::pending::<()>().await;
             }
+        };
+        if let Err(e) = run_actor(&mut rx, lifecycle, handler, false, stop, true).await {
+            eprintln!("Actor lifecycle error: {}", e);
         }
     });
 
-    (actor_ref, ActorHandle { handle })
+    (actor_ref, ActorHandle { handle, stop })
 }
 
 #[cfg(test)]
@@ -78,4 +319,100 @@ mod tests {
 
         actor_ref.send(TestMsg(42)).await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_lifecycle() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let record = |event: &'static str| {
+            let events = events.clone();
+            move || async move {
+                events.lock().unwrap().push(event);
+                Ok(())
+            }
+        };
+        let lifecycle = Lifecycle::new().on_start(record("start")).on_stop(record("stop"));
+        let handler_events = events.clone();
+        let (actor_ref, handle) = spawn_actor_with(10, lifecycle, move |_: TestMsg| {
+            let events = handler_events.clone();
+            async move {
+                events.lock().unwrap().push("message");
+                anyhow::bail!("handler errors are skipped")
+            }
+        });
+
+        actor_ref.send(TestMsg(1)).await.unwrap();
+        actor_ref.send(TestMsg(2)).await.unwrap();
+        // The stop hook runs once the mailbox is closed and drained
+        drop(actor_ref);
+        handle.join().await.unwrap();
+        assert_eq!(*events.lock().unwrap(), ["start", "message", "message", "stop"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_and_kill() {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let spawn = || {
+            let handled = handled.clone();
+            spawn_actor(10, move |TestMsg(n): TestMsg| {
+                let handled = handled.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    handled.lock().unwrap().push(n);
+                    Ok(())
+                }
+            })
+        };
+
+        // Stopping drains the mailbox even though a ref is still alive
+        let (actor_ref, handle) = spawn();
+        actor_ref.send(TestMsg(1)).await.unwrap();
+        actor_ref.send(TestMsg(2)).await.unwrap();
+        handle.stop().await.unwrap();
+        assert_eq!(*handled.lock().unwrap(), [1, 2]);
+        assert!(actor_ref.send(TestMsg(3)).await.is_err());
+
+        // Killing drops whatever is still queued
+        let (actor_ref, handle) = spawn();
+        actor_ref.send(TestMsg(4)).await.unwrap();
+        actor_ref.send(TestMsg(5)).await.unwrap();
+        handle.kill().await;
+        assert_eq!(*handled.lock().unwrap(), [1, 2]);
+        assert!(actor_ref.send(TestMsg(6)).await.is_err());
+    }
+
+    #[derive(Debug, Clone)]
+    enum Query {
+        Double(i32, ReplyTo<i32>),
+        Ignore(ReplyTo<i32>),
+        Stall(ReplyTo<i32>),
+    }
+
+    impl Message for Query {}
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ask() {
+        let (actor_ref, _handle) = spawn_actor(10, |msg: Query| async move {
+            match msg {
+                Query::Double(n, reply) => reply.send(n * 2)?,
+                Query::Ignore(reply) => drop(reply),
+                Query::Stall(reply) => {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    // Too late: the asker stopped waiting
+                    let _ = reply.send(0);
+                }
+            }
+            Ok(())
+        });
+
+        let timeout = Duration::from_millis(100);
+        let doubled = actor_ref.ask(|reply| Query::Double(21, reply), timeout).await.unwrap();
+        assert_eq!(doubled, Some(42));
+
+        // Dropping the reply channel ends the wait without an answer
+        let ignored = actor_ref.ask(Query::Ignore, timeout).await.unwrap();
+        assert_eq!(ignored, None);
+
+        let stalled = actor_ref.ask(Query::Stall, timeout).await.unwrap();
+        assert_eq!(stalled, None);
+    }
 }
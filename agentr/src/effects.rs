@@ -77,15 +77,15 @@ impl EffectContext {
             }
             Effect::Http => {
                 // Stub: would use reqwest in real implementation
-                Ok(format!("HTTP request to {}", args.get(0).unwrap_or(&"unknown".to_string())))
+                Ok(format!("HTTP request to {}", args.first().unwrap_or(&"unknown".to_string())))
             }
             Effect::FileRead => {
                 // Stub: would use tokio::fs in real implementation
-                Ok(format!("Read file: {}", args.get(0).unwrap_or(&"unknown".to_string())))
+                Ok(format!("Read file: {}", args.first().unwrap_or(&"unknown".to_string())))
             }
             Effect::FileWrite => {
                 // Stub
-                Ok(format!("Wrote to file: {}", args.get(0).unwrap_or(&"unknown".to_string())))
+                Ok(format!("Wrote to file: {}", args.first().unwrap_or(&"unknown".to_string())))
             }
         }
     }
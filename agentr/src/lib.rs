@@ -2,7 +2,13 @@
 pub mod actor;
 pub mod effects;
 pub mod mailbox;
+pub mod supervisor;
+pub mod system;
+pub mod timer;
 
-pub use actor::{spawn_actor, ActorHandle, ActorRef};
+pub use actor::{spawn_actor, spawn_actor_with, ActorHandle, ActorRef, Lifecycle, ReplyTo};
 pub use effects::{Capability, Effect, EffectContext};
 pub use mailbox::{Mailbox, Message};
+pub use supervisor::{ChildSpec, Strategy, Supervisor, SupervisorHandle};
+pub use system::ActorSystem;
+pub use timer::{send_after, send_every, TimerHandle};
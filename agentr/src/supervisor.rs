@@ -0,0 +1,524 @@
+// Supervision trees: restarting crashed actors
+use crate::actor::{run_actor, ActorRef, BoxFuture, Lifecycle};
+use crate::mailbox::Message;
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::{AbortHandle, JoinError};
+use tokio::time::Instant;
+
+/// Which children restart when one of them crashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Only the crashed child
+    OneForOne,
+    /// Every running child
+    OneForAll,
+    /// The crashed child and the running children started after it
+    RestForOne,
+}
+
+/// Completes once the supervisor asks a run of a child to stop
+type StopSignal = BoxFuture<()>;
+
+/// How to start, and restart, one child of a supervisor
+pub struct ChildSpec {
+    name: String,
+    /// Start a run of the child; the flag tells a restart from the first run
+    start: Arc<dyn Fn(bool, StopSignal) -> BoxFuture<Result<()>> + Send + Sync>,
+}
+
+impl ChildSpec {
+    /// A supervised actor. `make` builds a fresh handler, with its initial
+    /// state, for every (re)start; the mailbox, and so the returned ref,
+    /// survive restarts. A failing `make`, a handler error or a panic is a
+    /// crash; the actor stops normally once every ref is dropped.
+    pub fn actor<T, M, Init, F, Fut>(name: impl Into<String>, mailbox_size: usize, make: M) -> (Self, ActorRef<T>)
+    where
+        T: Message,
+        M: Fn() -> Init + Send + Sync + 'static,
+        Init: Future<Output = Result<F>> + Send + 'static,
+        F: Fn(T) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        Self::actor_with(name, mailbox_size, move || {
+            let init = make();
+            async move { Ok((Lifecycle::default(), init.await?)) }
+        })
+    }
+
+    /// A supervised actor with lifecycle hooks, built by `make` along with
+    /// its handler. A restarted actor runs its restart hook, then its start
+    /// hook. The run it replaces runs its stop hook first, whether it crashed
+    /// or a sibling's crash stopped it; a run that does not stop within the
+    /// supervisor's stop timeout is killed without it.
+    pub fn actor_with<T, M, Init, F, Fut>(name: impl Into<String>, mailbox_size: usize, make: M) -> (Self, ActorRef<T>)
+    where
+        T: Message,
+        M: Fn() -> Init + Send + Sync + 'static,
+        Init: Future<Output = Result<(Lifecycle, F)>> + Send + 'static,
+        F: Fn(T) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        let (tx, rx) = mpsc::channel::<T>(mailbox_size);
+        let mailbox = Arc::new(Mutex::new(rx));
+        let start = move |restarted: bool, stop: StopSignal| {
+            let mailbox = mailbox.clone();
+            let init = make();
+            Box::pin(async move {
+                let (mut lifecycle, handler) = init.await?;
+                // A predecessor releases the mailbox as its task ends, after
+                // its stop hook
+                let mut rx = mailbox.lock().await;
+                if restarted {
+                    lifecycle.restarted().await?;
+                }
+                // Messages still queued are left for the next run
+                run_actor(&mut rx, lifecycle, handler, true, stop, false).await
+            }) as BoxFuture<Result<()>>
+        };
+
+        let spec = Self {
+            name: name.into(),
+            start: Arc::new(start),
+        };
+        (spec, ActorRef::new(tx))
+    }
+
+    /// A nested supervisor. Giving up on its own children counts as its
+    /// crash, escalating the failure to the parent; stopping it stops its
+    /// children.
+    pub fn supervisor(supervisor: Supervisor) -> Self {
+        let name = supervisor.name.clone();
+        let supervisor = Arc::new(supervisor);
+        Self {
+            name,
+            start: Arc::new(move |_, stop| Box::pin(supervisor.clone().run(stop))),
+        }
+    }
+}
+
+/// Told the name and error of each crashed child that is restarted
+type FailureHook = Box<dyn Fn(&str, &anyhow::Error) + Send + Sync>;
+
+/// Restarts crashed children according to its strategy, giving up once they
+/// crash too often
+pub struct Supervisor {
+    name: String,
+    strategy: Strategy,
+    max_restarts: usize,
+    within: Duration,
+    stop_timeout: Duration,
+    children: Vec<ChildSpec>,
+    on_failure: Option<FailureHook>,
+}
+
+impl Supervisor {
+    /// A supervisor without children that allows 3 restarts within 5 seconds
+    /// and gives each child 5 seconds to stop
+    pub fn new(name: impl Into<String>, strategy: Strategy) -> Self {
+        Self {
+            name: name.into(),
+            strategy,
+            max_restarts: 3,
+            within: Duration::from_secs(5),
+            stop_timeout: Duration::from_secs(5),
+            children: Vec::new(),
+            on_failure: None,
+        }
+    }
+
+    /// Give up, stopping every child, once more than `max_restarts` restarts
+    /// fall within a window of `within`
+    pub fn max_restarts(mut self, max_restarts: usize, within: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.within = within;
+        self
+    }
+
+    /// How long a child stopped for a restart, or because the supervisor
+    /// gives up, may take to run its stop hook before it is killed
+    pub fn stop_timeout(mut self, timeout: Duration) -> Self {
+        self.stop_timeout = timeout;
+        self
+    }
+
+    /// Report each crash that is restarted to `hook`; one that makes the
+    /// supervisor give up is its result instead
+    pub fn on_failure(mut self, hook: impl Fn(&str, &anyhow::Error) + Send + Sync + 'static) -> Self {
+        self.on_failure = Some(Box::new(hook));
+        self
+    }
+
+    /// Add a child, started after those added before it
+    pub fn child(mut self, child: ChildSpec) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Start every child in order and supervise them as a root
+    pub fn start(self) -> SupervisorHandle {
+        SupervisorHandle {
+            handle: tokio::spawn(Arc::new(self).run(Box::pin(std::future::pending()))),
+        }
+    }
+
+    /// Supervise until every child has stopped normally or `stop` completes,
+    /// or fail once the restart intensity is exceeded
+    async fn run(self: Arc<Self>, mut stop: StopSignal) -> Result<()> {
+        let (exits, mut exited) = mpsc::unbounded_channel();
+        let mut children = Children {
+            running: self.children.iter().map(|_| None).collect(),
+            exits,
+            generation: 0,
+        };
+        for index in 0..self.children.len() {
+            children.start(&self, index, false);
+        }
+
+        let mut restarts: VecDeque<Instant> = VecDeque::new();
+        while children.running.iter().any(Option::is_some) {
+            let exit = tokio::select! {
+                exit = exited.recv() => exit,
+                () = &mut stop => {
+                    children.stop_all(self.stop_timeout).await;
+                    return Ok(());
+                }
+            };
+            let Some(exit) = exit else { break };
+            // Children stopped for a restart report too
+            if !children.is_current(exit.index, exit.generation) {
+                continue;
+            }
+            children.running[exit.index] = None;
+            let error = match exit.outcome {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e,
+                Err(e) => anyhow::anyhow!("{}", e),
+            };
+            let child = &self.children[exit.index].name;
+
+            let now = Instant::now();
+            restarts.push_back(now);
+            while restarts.front().is_some_and(|&at| now.duration_since(at) > self.within) {
+                restarts.pop_front();
+            }
+            if restarts.len() > self.max_restarts {
+                children.stop_all(self.stop_timeout).await;
+                anyhow::bail!(
+                    "Supervisor {} gave up after more than {} restarts within {:?}; child {} failed: {}",
+                    self.name,
+                    self.max_restarts,
+                    self.within,
+                    child,
+                    error
+                );
+            }
+            if let Some(hook) = &self.on_failure {
+                hook(child, &error);
+            }
+
+            let affected = |i: usize| i == exit.index || children.running[i].is_some();
+            let restart: Vec<usize> = match self.strategy {
+                Strategy::OneForOne => vec![exit.index],
+                Strategy::OneForAll => (0..self.children.len()).filter(|&i| affected(i)).collect(),
+                Strategy::RestForOne => (exit.index..self.children.len()).filter(|&i| affected(i)).collect(),
+            };
+            // Stop in reverse start order, then start again in order
+            for &index in restart.iter().rev() {
+                children.stop(index, self.stop_timeout).await;
+            }
+            for index in restart {
+                children.start(&self, index, true);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How one run of a child ended
+struct Exit {
+    index: usize,
+    generation: u64,
+    outcome: std::result::Result<Result<()>, JoinError>,
+}
+
+/// The current run of one child
+struct Run {
+    generation: u64,
+    task: AbortHandle,
+    stop: oneshot::Sender<()>,
+    /// Closed once the run's task has ended
+    done: oneshot::Receiver<()>,
+}
+
+/// The running children of one supervisor run; dropping it kills them all
+struct Children {
+    /// Per child spec: its current run
+    running: Vec<Option<Run>>,
+    exits: mpsc::UnboundedSender<Exit>,
+    generation: u64,
+}
+
+impl Children {
+    fn start(&mut self, supervisor: &Supervisor, index: usize, restarted: bool) {
+        self.generation += 1;
+        let generation = self.generation;
+        let (stop, stopped) = oneshot::channel();
+        // A dropped sender never stops the run
+        let signal = Box::pin(async {
+            if stopped.await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        });
+        let task = tokio::spawn((supervisor.children[index].start)(restarted, signal));
+        let (ended, done) = oneshot::channel::<()>();
+        self.running[index] = Some(Run {
+            generation,
+            task: task.abort_handle(),
+            stop,
+            done,
+        });
+
+        let exits = self.exits.clone();
+        tokio::spawn(async move {
+            let outcome = task.await;
+            drop(ended);
+            let _ = exits.send(Exit {
+                index,
+                generation,
+                outcome,
+            });
+        });
+    }
+
+    /// Ask a child to stop, so it runs its stop hook, and wait for it; one
+    /// that takes longer than `timeout` is killed
+    async fn stop(&mut self, index: usize, timeout: Duration) {
+        if let Some(run) = self.running[index].take() {
+            let _ = run.stop.send(());
+            if tokio::time::timeout(timeout, run.done).await.is_err() {
+                run.task.abort();
+            }
+        }
+    }
+
+    /// Stop every child, in reverse start order
+    async fn stop_all(&mut self, timeout: Duration) {
+        for index in (0..self.running.len()).rev() {
+            self.stop(index, timeout).await;
+        }
+    }
+
+    fn is_current(&self, index: usize, generation: u64) -> bool {
+        matches!(&self.running[index], Some(run) if run.generation == generation)
+    }
+}
+
+impl Drop for Children {
+    fn drop(&mut self) {
+        for run in self.running.iter().flatten() {
+            run.task.abort();
+        }
+    }
+}
+
+/// Handle to a running root supervisor
+pub struct SupervisorHandle {
+    handle: tokio::task::JoinHandle<Result<()>>,
+}
+
+impl SupervisorHandle {
+    /// Wait for the supervisor to finish; fails if it gave up on its children
+    pub async fn join(self) -> Result<()> {
+        self.handle.await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::ReplyTo;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Clone)]
+    enum Counter {
+        Incr,
+        Get(ReplyTo<usize>),
+        Fail,
+        Panic,
+    }
+
+    impl Message for Counter {}
+
+    /// A counter starting from zero on every restart
+    fn counter(name: &str) -> (ChildSpec, ActorRef<Counter>) {
+        ChildSpec::actor(name, 10, || async {
+            let count = Arc::new(AtomicUsize::new(0));
+            Ok(move |msg: Counter| {
+                let count = count.clone();
+                async move {
+                    match msg {
+                        Counter::Incr => {
+                            count.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Counter::Get(reply) => reply.send(count.load(Ordering::SeqCst))?,
+                        Counter::Fail => anyhow::bail!("failed on purpose"),
+                        Counter::Panic => panic!("panicked on purpose"),
+                    }
+                    Ok(())
+                }
+            })
+        })
+    }
+
+    async fn count(actor_ref: &ActorRef<Counter>) -> Option<usize> {
+        actor_ref.ask(Counter::Get, Duration::from_secs(1)).await.unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervisor() {
+        // Counts after `b` crashes, for children `a`, `b` and `c`
+        let cases = [
+            (Strategy::OneForOne, [1, 0, 1]),
+            (Strategy::OneForAll, [0, 0, 0]),
+            (Strategy::RestForOne, [1, 0, 0]),
+        ];
+        for (strategy, expected) in cases {
+            let (a_spec, a) = counter("a");
+            let (b_spec, b) = counter("b");
+            let (c_spec, c) = counter("c");
+            let _supervisor = Supervisor::new("root", strategy)
+                .child(a_spec)
+                .child(b_spec)
+                .child(c_spec)
+                .start();
+            for actor_ref in [&a, &b, &c] {
+                actor_ref.send(Counter::Incr).await.unwrap();
+            }
+            b.send(Counter::Panic).await.unwrap();
+
+            // `b` answers only once restarted, so the others are restarted too
+            let b_count = count(&b).await;
+            let counts = [count(&a).await, b_count, count(&c).await];
+            assert_eq!(counts, expected.map(Some), "{:?}", strategy);
+        }
+
+        // Exceeding the intensity escalates to the parent, which restarts the
+        // nested supervisor and with it the child
+        let (spec, child) = counter("child");
+        let nested = Supervisor::new("nested", Strategy::OneForOne)
+            .max_restarts(0, Duration::from_secs(10))
+            .child(spec);
+        let root = Supervisor::new("root", Strategy::OneForOne)
+            .max_restarts(1, Duration::from_secs(10))
+            .child(ChildSpec::supervisor(nested))
+            .start();
+        child.send(Counter::Incr).await.unwrap();
+        child.send(Counter::Fail).await.unwrap();
+        assert_eq!(count(&child).await, Some(0));
+
+        // A second escalation within the window makes the root give up too
+        child.send(Counter::Fail).await.unwrap();
+        let error = root.join().await.unwrap_err();
+        assert!(error.to_string().starts_with("Supervisor root gave up"));
+    }
+
+    type Events = Arc<std::sync::Mutex<Vec<String>>>;
+
+    /// A child recording its hooks as `<name> <event>`; its stop hook takes
+    /// `stopping` to finish
+    fn hooked(name: &'static str, events: &Events, stopping: Duration) -> (ChildSpec, ActorRef<Counter>) {
+        let events = events.clone();
+        ChildSpec::actor_with(name, 10, move || {
+            let events = events.clone();
+            async move {
+                let record = |event: &'static str| {
+                    let events = events.clone();
+                    move || async move {
+                        events.lock().unwrap().push(format!("{} {}", name, event));
+                        Ok(())
+                    }
+                };
+                let stop = record("stop");
+                let lifecycle = Lifecycle::new()
+                    .on_start(record("start"))
+                    .on_stop(move || async move {
+                        tokio::time::sleep(stopping).await;
+                        stop().await
+                    })
+                    .on_restart(record("restart"));
+                let handler = |msg: Counter| async move {
+                    match msg {
+                        Counter::Get(reply) => reply.send(0)?,
+                        Counter::Fail => anyhow::bail!("failed on purpose"),
+                        Counter::Panic => panic!("panicked on purpose"),
+                        Counter::Incr => {}
+                    }
+                    Ok(())
+                };
+                Ok((lifecycle, handler))
+            }
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restart_hooks() {
+        let events = Events::default();
+        let (spec, child) = hooked("child", &events, Duration::ZERO);
+        let failures = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook_failures = failures.clone();
+        let _supervisor = Supervisor::new("root", Strategy::OneForOne)
+            .on_failure(move |child, error| hook_failures.lock().unwrap().push(format!("{}: {}", child, error)))
+            .child(spec)
+            .start();
+
+        child.send(Counter::Fail).await.unwrap();
+        // Answered by the restarted run, once its hooks have run
+        assert_eq!(count(&child).await, Some(0));
+        assert_eq!(*events.lock().unwrap(), ["child start", "child stop", "child restart", "child start"]);
+        assert_eq!(*failures.lock().unwrap(), ["child: failed on purpose"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stopped_siblings_run_stop_hooks() {
+        // A panicking child and the siblings restarted with it run their stop
+        // hooks, in reverse start order, before any restarts
+        let events = Events::default();
+        let (a_spec, a) = hooked("a", &events, Duration::ZERO);
+        let (b_spec, b) = hooked("b", &events, Duration::ZERO);
+        let _supervisor = Supervisor::new("root", Strategy::OneForAll)
+            .child(a_spec)
+            .child(b_spec)
+            .start();
+        // Both running
+        count(&a).await;
+        count(&b).await;
+        b.send(Counter::Panic).await.unwrap();
+        assert_eq!(count(&b).await, Some(0));
+        assert_eq!(count(&a).await, Some(0));
+        let expected = ["a start", "b start", "b stop", "a stop", "a restart", "a start", "b restart", "b start"];
+        assert_eq!(*events.lock().unwrap(), expected);
+
+        // A sibling slower to stop than the timeout is killed without its hook
+        let events = Events::default();
+        let (a_spec, a) = hooked("a", &events, Duration::from_secs(60));
+        let (b_spec, b) = hooked("b", &events, Duration::ZERO);
+        let _supervisor = Supervisor::new("root", Strategy::RestForOne)
+            .stop_timeout(Duration::from_millis(100))
+            .child(b_spec)
+            .child(a_spec)
+            .start();
+        count(&a).await;
+        count(&b).await;
+        b.send(Counter::Fail).await.unwrap();
+        assert_eq!(count(&b).await, Some(0));
+        assert_eq!(count(&a).await, Some(0));
+        let expected = ["b start", "a start", "b stop", "b restart", "b start", "a restart", "a start"];
+        assert_eq!(*events.lock().unwrap(), expected);
+    }
+}
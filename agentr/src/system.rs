@@ -0,0 +1,123 @@
+// Actor system: owns spawned actors and shuts them down together
+use crate::actor::{spawn_actor_with, ActorHandle, ActorRef, Lifecycle};
+use crate::mailbox::Message;
+use anyhow::Result;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Keeps the handle of every actor spawned through it, so they can be
+/// stopped without dropping every `ActorRef` first
+pub struct ActorSystem {
+    actors: Mutex<Vec<ActorHandle>>,
+    shutdown_timeout: Duration,
+}
+
+impl Default for ActorSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ActorSystem {
+    /// A system that gives each actor 5 seconds to stop on shutdown
+    pub fn new() -> Self {
+        Self {
+            actors: Mutex::new(Vec::new()),
+            shutdown_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// How long `shutdown` waits for each actor before killing it
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Spawn an actor with a message handler
+    pub fn spawn<T, F, Fut>(&self, mailbox_size: usize, handler: F) -> ActorRef<T>
+    where
+        T: Message,
+        F: Fn(T) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        self.spawn_with(mailbox_size, Lifecycle::default(), handler)
+    }
+
+    /// Spawn an actor with a message handler and lifecycle hooks
+    pub fn spawn_with<T, F, Fut>(&self, mailbox_size: usize, lifecycle: Lifecycle, handler: F) -> ActorRef<T>
+    where
+        T: Message,
+        F: Fn(T) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        let (actor_ref, handle) = spawn_actor_with(mailbox_size, lifecycle, handler);
+        self.actors.lock().unwrap().push(handle);
+        actor_ref
+    }
+
+    /// Stop every actor spawned so far, one at a time in reverse spawn
+    /// order, so an actor can still message those spawned before it while
+    /// it drains. An actor exceeding the shutdown timeout is killed; the
+    /// rest are still stopped and the first failure is returned.
+    pub async fn shutdown(&self) -> Result<()> {
+        let actors = std::mem::take(&mut *self.actors.lock().unwrap());
+        let mut result = Ok(());
+        for handle in actors.into_iter().rev() {
+            let stopped = handle.stop_timeout(self.shutdown_timeout).await;
+            result = result.and(stopped);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Debug, Clone)]
+    struct Work(usize);
+
+    impl Message for Work {}
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let system = ActorSystem::new().shutdown_timeout(Duration::from_millis(100));
+
+        let stopped = events.clone();
+        let sink_events = events.clone();
+        let sink = system.spawn_with(
+            10,
+            Lifecycle::new().on_stop(move || async move {
+                stopped.lock().unwrap().push("sink stopped".to_string());
+                Ok(())
+            }),
+            move |Work(n): Work| {
+                let events = sink_events.clone();
+                async move {
+                    events.lock().unwrap().push(format!("sink {}", n));
+                    Ok(())
+                }
+            },
+        );
+        // Forwards to the sink, which is only stopped once this has drained
+        let forwarder = system.spawn(10, move |work: Work| {
+            let sink = sink.clone();
+            async move { sink.send(work).await }
+        });
+        let stuck = system.spawn(10, |_: Work| std::future::pending());
+
+        for n in 0..3 {
+            forwarder.send(Work(n)).await.unwrap();
+        }
+        stuck.send(Work(0)).await.unwrap();
+
+        let error = system.shutdown().await.unwrap_err();
+        assert!(error.to_string().starts_with("Actor did not stop within"));
+        assert_eq!(*events.lock().unwrap(), ["sink 0", "sink 1", "sink 2", "sink stopped"]);
+        // Stopped actors no longer accept messages
+        assert!(forwarder.send(Work(3)).await.is_err());
+    }
+}
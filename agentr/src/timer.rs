@@ -0,0 +1,137 @@
+// Timer service: delayed and periodic delivery to actors
+use crate::actor::ActorRef;
+use crate::mailbox::Message;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimerState {
+    Armed,
+    /// Delivered its last message, or its target is gone
+    Done,
+    Cancelled,
+}
+
+/// Handle to a scheduled delivery. Dropping it leaves the timer running;
+/// only `cancel` stops it.
+#[derive(Debug)]
+pub struct TimerHandle {
+    state: Arc<Mutex<TimerState>>,
+    task: AbortHandle,
+}
+
+impl TimerHandle {
+    /// Stop any delivery that has not started yet. Returns whether the timer
+    /// was still armed, i.e. whether this call prevented a delivery.
+    pub fn cancel(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if *state != TimerState::Armed {
+            return false;
+        }
+        *state = TimerState::Cancelled;
+        self.task.abort();
+        true
+    }
+
+    /// Whether deliveries are still to come
+    pub fn is_armed(&self) -> bool {
+        *self.state.lock().unwrap() == TimerState::Armed
+    }
+}
+
+/// Deliver `msg` to `target` once `delay` has passed
+pub fn send_after<T: Message>(target: ActorRef<T>, delay: Duration, msg: T) -> TimerHandle {
+    let state = Arc::new(Mutex::new(TimerState::Armed));
+    let timer_state = state.clone();
+    let task = tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        {
+            let mut state = timer_state.lock().unwrap();
+            if *state != TimerState::Armed {
+                return;
+            }
+            *state = TimerState::Done;
+        }
+        let _ = target.send(msg).await;
+    });
+
+    TimerHandle {
+        state,
+        task: task.abort_handle(),
+    }
+}
+
+/// Deliver a copy of `msg` to `target` every `period`, starting one period
+/// from now, until cancelled or the target stops. Ticks the actor falls
+/// behind on are delayed rather than delivered in a burst.
+///
+/// Panics if `period` is zero, like `tokio::time::interval`.
+pub fn send_every<T: Message>(target: ActorRef<T>, period: Duration, msg: T) -> TimerHandle {
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let state = Arc::new(Mutex::new(TimerState::Armed));
+    let timer_state = state.clone();
+    let task = tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            if *timer_state.lock().unwrap() != TimerState::Armed {
+                return;
+            }
+            if target.send(msg.clone()).await.is_err() {
+                let mut state = timer_state.lock().unwrap();
+                if *state == TimerState::Armed {
+                    *state = TimerState::Done;
+                }
+                return;
+            }
+        }
+    });
+
+    TimerHandle {
+        state,
+        task: task.abort_handle(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::spawn_actor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Clone)]
+    struct Tick;
+
+    impl Message for Tick {}
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timers() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counter = ticks.clone();
+        let (actor_ref, _handle) = spawn_actor(10, move |_: Tick| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+
+        let once = send_after(actor_ref.clone(), Duration::from_millis(50), Tick);
+        let cancelled = send_after(actor_ref.clone(), Duration::from_millis(50), Tick);
+        assert!(cancelled.cancel());
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 1);
+        // Already delivered: nothing left to cancel
+        assert!(!once.cancel());
+
+        let every = send_every(actor_ref, Duration::from_millis(100), Tick);
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert!(every.is_armed());
+        assert!(every.cancel());
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 4);
+    }
+}